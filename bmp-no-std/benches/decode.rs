@@ -0,0 +1,48 @@
+//! Decoding benchmarks, run with `cargo bench --features std`.
+
+#[macro_use]
+extern crate bmp;
+#[macro_use]
+extern crate criterion;
+
+use bmp::consts::{BLACK, RED, WHITE};
+use bmp::{Image, Pixel};
+use criterion::{black_box, BenchmarkId, Criterion, Throughput};
+
+const SIZES: [u32; 3] = [64, 256, 1024];
+
+// A grayscale image using every entry of a 256 color palette
+fn grayscale(size: u32) -> Image {
+    let mut img = Image::new(size, size);
+    for (x, y) in img.coordinates() {
+        let v = ((x + y) % 256) as u8;
+        img.set_pixel(x, y, px!(v, v, v));
+    }
+    img
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    for &size in SIZES.iter() {
+        let checkerboard = Image::checkerboard(size, size, RED, WHITE, 3);
+        let mut gradient = Image::gradient_horizontal(size, size, BLACK, WHITE);
+        gradient.set_pixel(0, 0, RED);
+        let files = [
+            ("24-bit", checkerboard.to_bytes()),
+            ("8-bit", grayscale(size).to_bytes_indexed(256).unwrap()),
+            ("4-bit", gradient.to_bytes_indexed(16).unwrap()),
+            ("1-bit", checkerboard.to_bytes_indexed(2).unwrap()),
+        ];
+
+        group.throughput(Throughput::Elements(size as u64 * size as u64));
+        for &(name, ref bytes) in files.iter() {
+            group.bench_with_input(BenchmarkId::new(name, size), bytes, |b, bytes| {
+                b.iter(|| bmp::from_bytes(black_box(bytes)).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
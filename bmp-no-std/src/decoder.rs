@@ -1,7 +1,10 @@
 // The BmpHeader always has a size of 14 bytes
 const BMP_HEADER_SIZE: u64 = 14;
 
+use core::cmp;
 use core::convert::TryInto;
+use core::fmt;
+use core::mem;
 
 use alloc::{string::String};
 
@@ -25,25 +28,62 @@ pub fn u16_from_slice(slice: &[u8]) -> u16 {
 pub struct BmpError {
     pub kind: BmpErrorKind,
     pub details: String,
+    /// The byte offset in the file of the field or the data that could not be decoded, if the
+    /// error comes from a decoding stage.
+    pub offset: Option<usize>,
 }
 
 impl BmpError {
-    fn new<T: AsRef<str>>(kind: BmpErrorKind, details: T) -> BmpError {
+    pub(crate) fn new<T: AsRef<str>>(kind: BmpErrorKind, details: T) -> BmpError {
         BmpError {
             kind: kind,
             details: String::from(details.as_ref()),
+            offset: None,
         }
     }
+
+    pub(crate) fn at(mut self, offset: usize) -> BmpError {
+        self.offset = Some(offset);
+        self
+    }
+}
+
+/// Prints the kind of the error followed by its details, e.g.
+/// `Truncated file: The file ends too early`.
+impl fmt::Display for BmpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.as_ref(), self.details)
+    }
+}
+
+// `core::error::Error` isn't stable, so the trait is only implemented with std
+#[cfg(feature = "std")]
+impl std::error::Error for BmpError {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for BmpError {
+    fn from(err: std::io::Error) -> BmpError {
+        use alloc::string::ToString;
+        BmpError::new(Io, err.to_string())
+    }
 }
 
 /// The different kinds of possible BMP errors.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum BmpErrorKind {
     WrongMagicNumbers,
     UnsupportedBitsPerPixel,
     UnsupportedCompressionType,
     UnsupportedBmpVersion,
     UnsupportedHeader,
+    DimensionMismatch,
+    InvalidPalette,
+    OutOfBounds,
+    InvalidDimensions,
+    ImageTooLarge,
+    InvalidPaletteIndex,
+    Io,
+    Truncated,
 }
 
 impl AsRef<str> for BmpErrorKind {
@@ -53,50 +93,263 @@ impl AsRef<str> for BmpErrorKind {
             UnsupportedBitsPerPixel => "Unsupported bits per pixel",
             UnsupportedCompressionType => "Unsupported compression type",
             UnsupportedBmpVersion => "Unsupported BMP version",
+            DimensionMismatch => "Dimension mismatch",
+            InvalidPalette => "Invalid palette",
+            OutOfBounds => "Out of bounds",
+            InvalidDimensions => "Invalid dimensions",
+            ImageTooLarge => "Image too large",
+            InvalidPaletteIndex => "Invalid palette index",
+            Io => "I/O error",
+            Truncated => "Truncated file",
             _ => "BMP Error",
         }
     }
 }
 
 pub fn decode_image(bmp_data: &[u8]) -> BmpResult<Image> {
+    decode_image_with_limit(bmp_data, DEFAULT_MAX_PIXELS)
+}
+
+pub fn decode_image_with_limit(bmp_data: &[u8], max_pixels: usize) -> BmpResult<Image> {
     read_bmp_id(bmp_data)?;
     let header = read_bmp_header(bmp_data)?;
     let dib_header = read_bmp_dib_header(bmp_data)?;
+    check_pixel_count(&dib_header, max_pixels)?;
 
     let color_palette = read_color_palette(bmp_data, &dib_header)?;
 
-    let width = dib_header.width.abs() as u32;
-    let height = dib_header.height.abs() as u32;
-    let padding = width % 4;
+    let mut data = Vec::new();
+    read_data(bmp_data, &header, &dib_header, &color_palette, &mut data)?;
+    Ok(build_image(header, &dib_header, color_palette, data))
+}
 
-    let data = match color_palette {
-        Some(ref palette) => {
-            read_indexes(
-                bmp_data,
-                &palette,
-                width as usize,
-                height as usize,
-                dib_header.bits_per_pixel,
-                header.pixel_offset as usize,
-            )?
+pub fn decode_from_iter<I: Iterator<Item = u8>>(mut iter: I) -> BmpResult<Image> {
+    // The headers and the palette are buffered up to the start of the pixels
+    let mut head = Vec::new();
+    pull(&mut iter, &mut head, 2, 0)?;
+    read_bmp_id(&head)?;
+    pull(&mut iter, &mut head, BMP_HEADER_SIZE as usize, 0)?;
+    let header = read_bmp_header(&head)?;
+    pull(&mut iter, &mut head, header.pixel_offset as usize, 0)?;
+    let dib_header = read_bmp_dib_header(&head)?;
+    check_pixel_count(&dib_header, DEFAULT_MAX_PIXELS)?;
+    let color_palette = read_color_palette(&head, &dib_header)?;
+
+    let width = dib_header.width.abs() as usize;
+    let height = dib_header.height.abs() as usize;
+    let pixel_offset = header.pixel_offset as usize;
+    // The pixels are then decoded from buffers holding only the pixel data
+    let data_header = BmpHeader {
+        pixel_offset: 0,
+        ..header.clone()
+    };
+    let shift = |base: usize| {
+        move |mut err: BmpError| {
+            err.offset = err.offset.map(|offset| base + offset);
+            err
         }
-        None => read_pixels(bmp_data, width, height, header.pixel_offset)?,
     };
 
-    let image = Image {
+    let mut data = Vec::with_capacity(width * height);
+    match CompressionType::from_u32(dib_header.compress_type) {
+        CompressionType::Rle8bit | CompressionType::Rle4bit => {
+            // The end of run-length encoded data is only known once it is decoded
+            let rest: Vec<u8> = iter.collect();
+            read_data(&rest, &data_header, &dib_header, &color_palette, &mut data)
+                .map_err(shift(pixel_offset))?;
+        }
+        _ => {
+            // Decode one row at a time, so the whole pixel array is never in memory
+            let mut row_header = dib_header.clone();
+            row_header.height = 1;
+            let bpp = dib_header.bits_per_pixel as usize;
+            let row_size = (bpp * width + 31) / 32 * 4;
+            let mut row = Vec::with_capacity(row_size);
+            for y in 0..height {
+                let base = pixel_offset + y * row_size;
+                // The last row doesn't need its padding
+                let len = if y + 1 == height { (bpp * width + 7) / 8 } else { row_size };
+                row.clear();
+                pull(&mut iter, &mut row, len, base)?;
+                read_data(&row, &data_header, &row_header, &color_palette, &mut data)
+                    .map_err(shift(base))?;
+            }
+        }
+    }
+    Ok(build_image(header, &dib_header, color_palette, data))
+}
+
+// Appends bytes of `iter` to `buf` until it holds `len` bytes. Errors report their offset as
+// `base` plus the length of `buf`.
+fn pull<I: Iterator<Item = u8>>(
+    iter: &mut I,
+    buf: &mut Vec<u8>,
+    len: usize,
+    base: usize,
+) -> BmpResult<()> {
+    while buf.len() < len {
+        match iter.next() {
+            Some(byte) => buf.push(byte),
+            None => {
+                return Err(
+                    BmpError::new(Truncated, "The file ends too early").at(base + buf.len())
+                )
+            }
+        }
+    }
+    Ok(())
+}
+
+fn build_image(
+    header: BmpHeader,
+    dib_header: &BmpDibHeader,
+    color_palette: Option<Palette>,
+    data: Vec<Pixel>,
+) -> Image {
+    let width = dib_header.width.abs() as u32;
+    let height = dib_header.height.abs() as u32;
+    let mut image_dib_header = BmpDibHeader::new(width as i32, height as i32);
+    if let Some(ref palette) = color_palette {
+        // Keep the bit depth of indexed images, so the palette can still be indexed
+        // RLE images aren't bounded by the file length, so the size may not fit in the header
+        let bpp = dib_header.bits_per_pixel as u64;
+        let data_size = (bpp * width as u64 + 31) / 32 * 4 * height as u64;
+        image_dib_header.bits_per_pixel = dib_header.bits_per_pixel;
+        image_dib_header.data_size = cmp::min(data_size, u32::MAX as u64) as u32;
+        image_dib_header.num_colors = palette.len() as u32;
+    }
+
+    let padding = row_padding(width, image_dib_header.bits_per_pixel);
+    Image {
         header,
-        dib_header: BmpDibHeader::new(width as i32, height as i32),
+        dib_header: image_dib_header,
         color_palette,
         width,
         height,
         padding,
         data,
-    };
+    }
+}
+
+pub fn decode_into(bmp_data: &[u8], buf: &mut Vec<Pixel>) -> BmpResult<(u32, u32)> {
+    read_bmp_id(bmp_data)?;
+    let header = read_bmp_header(bmp_data)?;
+    let dib_header = read_bmp_dib_header(bmp_data)?;
+    check_pixel_count(&dib_header, DEFAULT_MAX_PIXELS)?;
+    let color_palette = read_color_palette(bmp_data, &dib_header)?;
+
+    buf.clear();
+    read_data(bmp_data, &header, &dib_header, &color_palette, buf)?;
+    Ok((dib_header.width.abs() as u32, dib_header.height.abs() as u32))
+}
 
-    Ok(image)
+pub fn decode_thumbnail(bmp_data: &[u8]) -> BmpResult<Option<Image>> {
+    if bmp_data.len() < THUMBNAIL_TRAILER_SIZE {
+        return Ok(None);
+    }
+    let trailer = &bmp_data[bmp_data.len() - THUMBNAIL_TRAILER_SIZE..];
+    if trailer[..4] != THUMBNAIL_MAGIC[..] {
+        return Ok(None);
+    }
+
+    let offset = u32_from_slice(&trailer[4..8]) as usize;
+    let size = u32_from_slice(&trailer[8..12]) as usize;
+    match offset.checked_add(size) {
+        Some(end) if end <= bmp_data.len() - THUMBNAIL_TRAILER_SIZE => {
+            decode_image(&bmp_data[offset..end]).map(Some)
+        }
+        _ => Err(
+            BmpError::new(OutOfBounds, "The thumbnail trailer points outside of the file")
+                .at(bmp_data.len() - THUMBNAIL_TRAILER_SIZE),
+        ),
+    }
+}
+
+// Appends the pixels of every row to `data`, in the bottom-up order they are stored in
+fn read_data(
+    bmp_data: &[u8],
+    header: &BmpHeader,
+    dib_header: &BmpDibHeader,
+    color_palette: &Option<Palette>,
+    data: &mut Vec<Pixel>,
+) -> BmpResult<()> {
+    let width = dib_header.width.abs() as u32;
+    let height = dib_header.height.abs() as u32;
+    let rle = matches!(
+        CompressionType::from_u32(dib_header.compress_type),
+        CompressionType::Rle8bit | CompressionType::Rle4bit
+    );
+    // The length of run-length encoded data is only known once it is decoded. Otherwise, every
+    // row but the last one is padded to a multiple of 4 bytes.
+    if !rle {
+        let bpp = dib_header.bits_per_pixel as u64;
+        let row_size = (bpp * width as u64 + 31) / 32 * 4;
+        let end = header.pixel_offset as u64
+            + row_size * (height as u64 - 1)
+            + (bpp * width as u64 + 7) / 8;
+        check_length(bmp_data, end.min(usize::MAX as u64) as usize)?;
+    }
+
+    // Reserve the whole image once, rows are then extended without reallocating
+    data.reserve_exact(width as usize * height as usize);
+
+    match *color_palette {
+        Some(ref palette) if rle => read_indexes_rle(
+            bmp_data,
+            palette.colors(),
+            width as usize,
+            height as usize,
+            dib_header.bits_per_pixel,
+            header.pixel_offset as usize,
+            data,
+        ),
+        Some(ref palette) => read_indexes(
+            bmp_data,
+            palette.colors(),
+            width as usize,
+            height as usize,
+            dib_header.bits_per_pixel,
+            header.pixel_offset as usize,
+            data,
+        ),
+        None => match dib_header.bits_per_pixel {
+            bpp @ 16 | bpp @ 32 => read_pixels_bitfields(
+                bmp_data,
+                width,
+                height,
+                header.pixel_offset,
+                bpp,
+                bitfield_masks(dib_header),
+                data,
+            ),
+            _ => read_pixels(bmp_data, width, height, header.pixel_offset, data),
+        },
+    }
+}
+
+// The dimensions have been validated by `read_bmp_dib_header`, so the product can't overflow
+fn check_pixel_count(dib_header: &BmpDibHeader, max_pixels: usize) -> BmpResult<()> {
+    let count = dib_header.width as usize * dib_header.height.abs() as usize;
+    if count > max_pixels {
+        return Err(BmpError::new(
+            ImageTooLarge,
+            "The image has more pixels than the decoding limit",
+        )
+        .at(18));
+    }
+    Ok(())
+}
+
+// Returns a `Truncated` error if the file ends before `end`, the end of the next read
+fn check_length(bmp_data: &[u8], end: usize) -> BmpResult<()> {
+    if bmp_data.len() < end {
+        return Err(BmpError::new(Truncated, "The file ends too early").at(bmp_data.len()));
+    }
+    Ok(())
 }
 
 fn read_bmp_id(bmp_data: &[u8]) -> BmpResult<()> {
+    check_length(bmp_data, 2)?;
     let mut bm = [0, 0];
     bm.clone_from_slice(&bmp_data[..2]);
 
@@ -106,11 +359,13 @@ fn read_bmp_id(bmp_data: &[u8]) -> BmpResult<()> {
         Err(BmpError::new(
             WrongMagicNumbers,
             "Expected [66, 77], but was {:?}",
-        ))
+        )
+        .at(0))
     }
 }
 
 fn read_bmp_header(bmp_data: &[u8]) -> BmpResult<BmpHeader> {
+    check_length(bmp_data, BMP_HEADER_SIZE as usize)?;
     let header = BmpHeader {
         file_size: u32_from_slice(&bmp_data[2..6]),
         creator1: u16_from_slice(&bmp_data[6..8]),
@@ -122,54 +377,106 @@ fn read_bmp_header(bmp_data: &[u8]) -> BmpResult<BmpHeader> {
 }
 
 fn read_bmp_dib_header(bmp_data: &[u8]) -> BmpResult<BmpDibHeader> {
-    let dib_header = BmpDibHeader {
+    check_length(bmp_data, 54)?;
+    let mut dib_header = BmpDibHeader {
         header_size: u32_from_slice(&bmp_data[14..18]),
         width: u32_from_slice(&bmp_data[18..22]) as i32,
         height: u32_from_slice(&bmp_data[22..26]) as i32,
         num_planes: u16_from_slice(&bmp_data[26..28]),
         bits_per_pixel: u16_from_slice(&bmp_data[28..30]),
         compress_type: u32_from_slice(&bmp_data[30..34]),
+        // Allowed to be 0 for uncompressed images, so the size of the pixels is only ever
+        // computed from the dimensions and the bit depth
         data_size: u32_from_slice(&bmp_data[34..38]),
         hres: u32_from_slice(&bmp_data[38..42]) as i32,
         vres: u32_from_slice(&bmp_data[42..46]) as i32,
         num_colors: u32_from_slice(&bmp_data[46..50]),
         num_imp_colors: u32_from_slice(&bmp_data[50..54]),
+        red_mask: 0,
+        green_mask: 0,
+        blue_mask: 0,
+        alpha_mask: 0,
     };
 
+    // The color masks directly follow the 40 bytes of the v3 header. They are part of the header
+    // itself for the 52 and 56-byte extensions and v4 and v5, and are appended to a plain v3
+    // header when it uses BITFIELDS compression. Only headers of 56 bytes or more have an alpha
+    // mask.
+    if dib_header.header_size >= 52 || dib_header.compress_type == 3 {
+        check_length(bmp_data, 66)?;
+        dib_header.red_mask = u32_from_slice(&bmp_data[54..58]);
+        dib_header.green_mask = u32_from_slice(&bmp_data[58..62]);
+        dib_header.blue_mask = u32_from_slice(&bmp_data[62..66]);
+    }
+    if dib_header.header_size >= 56 {
+        check_length(bmp_data, 70)?;
+        dib_header.alpha_mask = u32_from_slice(&bmp_data[66..70]);
+    }
+
     match BmpVersion::from_dib_header(&dib_header) {
         // V3 is the only version that is "fully" supported (decompressed images are the exception)
-        // We will also attempt to decode v4 and v5, but we ignore all the additional data in the header.
-        // This should not impose a big problem because neither decompression, nor 16 and 32-bit images are supported,
-        // so the decoding will likely fail due to these constraints either way.
+        // We will also attempt to decode v4 and v5, but we ignore all the additional data in the
+        // header apart from the color masks, which are needed for 16 and 32-bit images.
+        // The 52 and 56-byte extensions are v3 headers with additional color masks.
+        // The NT variant is a v3 header followed by the masks of a BITFIELDS image.
         Some(BmpVersion::Three) |
+        Some(BmpVersion::ThreeNT) |
+        Some(BmpVersion::ThreeRgbMasks) |
+        Some(BmpVersion::ThreeRgbaMasks) |
         Some(BmpVersion::Four) |
         Some(BmpVersion::Five) => (),
         // Otherwise, report the errors
-        Some(other) => return Err(BmpError::new(UnsupportedBmpVersion, other)),
+        Some(other) => return Err(BmpError::new(UnsupportedBmpVersion, other).at(14)),
         None => {
             return Err(BmpError::new(
                 UnsupportedHeader,
                 "Only simple BMP images of version 3, 4, and 5 are currently supported. \
                 Connot decode the image for the following header: {:?}",
-                ),
+                )
+                .at(14),
             );
         }
     }
 
+    // The image must have pixels, and a buffer of all of them must be allocatable. A negative
+    // height is a top-down image, but its magnitude must still fit in an `i32`.
+    let pixel_count = dib_header
+        .height
+        .checked_abs()
+        .filter(|_| dib_header.width > 0 && dib_header.height != 0)
+        .and_then(|height| (dib_header.width as usize).checked_mul(height as usize));
+    let allocatable = pixel_count
+        .and_then(|count| count.checked_mul(mem::size_of::<Pixel>()))
+        .map_or(false, |size| size <= isize::MAX as usize);
+    if !allocatable {
+        return Err(BmpError::new(
+            InvalidDimensions,
+            "The width must be positive, the height non-zero, and the pixels must fit in memory",
+        )
+        .at(18));
+    }
+
     match dib_header.bits_per_pixel {
         // Currently supported
-        1 | 4 | 8 | 24 => (),
+        1 | 4 | 8 | 16 | 24 | 32 => (),
         _other => {
             return Err(BmpError::new(
                 UnsupportedBitsPerPixel,
-                "Only 1, 4, 8, and 24 bits per pixel are currently supported, was: {}",
-            ))
+                "Only 1, 4, 8, 16, 24, and 32 bits per pixel are currently supported, was: {}",
+            )
+            .at(28))
         }
     }
 
     match CompressionType::from_u32(dib_header.compress_type) {
         CompressionType::Uncompressed => (),
-        other => return Err(BmpError::new(UnsupportedCompressionType, other)),
+        // The masks are only meaningful for 16 and 32-bit images
+        CompressionType::BitfieldsEncoding
+            if dib_header.bits_per_pixel == 16 || dib_header.bits_per_pixel == 32 => (),
+        // Each run-length encoding has a single bit depth
+        CompressionType::Rle8bit if dib_header.bits_per_pixel == 8 => (),
+        CompressionType::Rle4bit if dib_header.bits_per_pixel == 4 => (),
+        other => return Err(BmpError::new(UnsupportedCompressionType, other).at(30)),
     }
 
     Ok(dib_header)
@@ -178,8 +485,10 @@ fn read_bmp_dib_header(bmp_data: &[u8]) -> BmpResult<BmpDibHeader> {
 fn read_color_palette(
     bmp_data: &[u8],
     dh: &BmpDibHeader,
-) -> BmpResult<Option<Vec<Pixel>>> {
+) -> BmpResult<Option<Palette>> {
     let num_entries = match dh.bits_per_pixel {
+        // Images with more than 8 bits per pixel are never indexed, a color table is only a hint
+        bpp if bpp > 8 => return Ok(None),
         // We have a color_palette if the num_colors in the dib header is not zero
         _ if dh.num_colors != 0 => dh.num_colors as usize,
         // Or if there are 8 or less bits per pixel
@@ -189,13 +498,29 @@ fn read_color_palette(
 
     let num_bytes = match BmpVersion::from_dib_header(&dh) {
         // Three bytes for v2. Though, this is currently not supported
-        Some(BmpVersion::Two) => return Err(BmpError::new(UnsupportedBmpVersion, BmpVersion::Two)),
+        Some(BmpVersion::Two) => {
+            return Err(BmpError::new(UnsupportedBmpVersion, BmpVersion::Two).at(14))
+        }
         // Each entry in the color_palette is four bytes for v3, v4, and v5
         _ => 4,
     };
 
-
+    // The palette directly follows the DIB header. The pixels are always read from
+    // `pixel_offset`, which can leave a gap after the palette, e.g. for an ICC profile.
     let offset = (BMP_HEADER_SIZE + dh.header_size as u64) as usize;
+    if num_entries > 1 << dh.bits_per_pixel {
+        return Err(BmpError::new(
+            InvalidPalette,
+            "The palette has more colors than the bits per pixel can index",
+        )
+        .at(46));
+    }
+    if offset + num_entries * num_bytes > bmp_data.len() {
+        return Err(
+            BmpError::new(InvalidPalette, "The palette extends past the end of the file")
+                .at(bmp_data.len()),
+        );
+    }
     let px = &mut [0; 4][0..num_bytes as usize];
     let mut color_palette = Vec::with_capacity(num_entries);
     for i in 0..num_entries {
@@ -203,20 +528,24 @@ fn read_color_palette(
         color_palette.push(px!(px[2], px[1], px[0]));
     }
 
-    Ok(Some(color_palette))
+    Ok(Some(Palette::new(color_palette)))
 }
 
 fn read_indexes(
     bmp_data: &[u8],
-    palette: &Vec<Pixel>,
+    palette: &[Pixel],
     width: usize,
     height: usize,
     bpp: u16,
     offset: usize,
-) -> BmpResult<Vec<Pixel>> {
-    let mut data = Vec::with_capacity(height * width);
-    // Number of bytes to read from each row, varies based on bits_per_pixel
-    let bytes_per_row = (width as f64 / (8.0 / bpp as f64)) as usize;
+    data: &mut Vec<Pixel>,
+) -> BmpResult<()> {
+    // Number of bytes to read from each row, varies based on bits_per_pixel.
+    // A partially used last byte still counts, so round up.
+    let bpp = bpp as usize;
+    let bytes_per_row = (width * bpp + 7) / 8;
+    let per_byte = 8 / bpp;
+    let table = unpack_table(palette, bpp);
     for y in 0..height {
         let padding = match bytes_per_row % 4 {
             0 => 0,
@@ -225,11 +554,135 @@ fn read_indexes(
         let start = offset + (bytes_per_row + padding) * y;
         let bytes = &bmp_data[start..start + bytes_per_row];
 
-        for i in bit_index(&bytes, bpp as usize, width as usize) {
-            data.push(palette[i]);
+        // With a full 8-bit palette every byte is a valid index to a single pixel
+        if palette.len() >= 256 && bpp == 8 {
+            data.extend(bytes.iter().map(|&i| table[i as usize].pixels[0]));
+            continue;
+        }
+        for (n, &byte) in bytes.iter().enumerate() {
+            let count = cmp::min(per_byte, width - n * per_byte);
+            let unpacked = &table[byte as usize];
+            // A corrupt file can reference colors past the end of a short palette
+            if unpacked.valid < count {
+                return Err(BmpError::new(
+                    InvalidPaletteIndex,
+                    "A pixel references a color outside of the palette",
+                )
+                .at(start + n));
+            }
+            data.extend_from_slice(&unpacked.pixels[..count]);
+        }
+    }
+    Ok(())
+}
+
+// The pixels packed in a byte of indexes, and how many of the leading ones are in the palette.
+#[derive(Clone, Copy)]
+struct Unpacked {
+    pixels: [Pixel; 8],
+    valid: usize,
+}
+
+// Unpacks every possible byte once, so that rows are then decoded a byte at a time. Pixels are
+// packed from the most significant bits of each byte.
+fn unpack_table(palette: &[Pixel], bpp: usize) -> [Unpacked; 256] {
+    let mask = (1 << bpp) - 1;
+    let mut table = [Unpacked { pixels: [consts::BLACK; 8], valid: 0 }; 256];
+    for (byte, unpacked) in table.iter_mut().enumerate() {
+        for k in 0..8 / bpp {
+            match palette.get((byte >> (8 - bpp * (k + 1))) & mask) {
+                Some(&px) => unpacked.pixels[k] = px,
+                None => break,
+            }
+            unpacked.valid += 1;
+        }
+    }
+    table
+}
+
+// Decodes RLE8 or RLE4 data. The indexes are first decoded into a full image, since a delta can
+// skip pixels, which then take the first color of the palette.
+fn read_indexes_rle(
+    bmp_data: &[u8],
+    palette: &[Pixel],
+    width: usize,
+    height: usize,
+    bpp: u16,
+    offset: usize,
+    data: &mut Vec<Pixel>,
+) -> BmpResult<()> {
+    let mut indexes = alloc::vec![0u8; width * height];
+    // Runs crossing the end of a row or of the image are clipped
+    let mut put = |x: usize, y: usize, index: u8| {
+        if x < width && y < height {
+            indexes[y * width + x] = index;
+        }
+    };
+    let bytes = |start: usize, len: usize| {
+        check_length(bmp_data, start + len).map(|_| &bmp_data[start..start + len])
+    };
+    // The `i`-th index of a run or an absolute run, 4-bit indexes are stored high nibble first
+    let nibble = |byte: u8, i: usize| match (bpp, i % 2) {
+        (8, _) => byte,
+        (_, 0) => byte >> 4,
+        _ => byte & 0x0F,
+    };
+
+    let (mut x, mut y, mut pos) = (0, 0, offset);
+    while y < height {
+        let pair = bytes(pos, 2)?;
+        pos += 2;
+        match (pair[0] as usize, pair[1]) {
+            // An encoded run of `count` pixels
+            (count, value) if count > 0 => {
+                for i in 0..count {
+                    put(x + i, y, nibble(value, i));
+                }
+                x += count;
+            }
+            // End of line
+            (_, 0) => {
+                x = 0;
+                y += 1;
+            }
+            // End of bitmap
+            (_, 1) => break,
+            // Delta, moving right and up
+            (_, 2) => {
+                let delta = bytes(pos, 2)?;
+                pos += 2;
+                x += delta[0] as usize;
+                y += delta[1] as usize;
+            }
+            // Absolute mode, padded to a 16-bit boundary
+            (_, count) => {
+                let count = count as usize;
+                let len = if bpp == 8 { count } else { (count + 1) / 2 };
+                let absolute = bytes(pos, len)?;
+                pos += len + len % 2;
+                for i in 0..count {
+                    let byte = if bpp == 8 { absolute[i] } else { absolute[i / 2] };
+                    put(x + i, y, nibble(byte, i));
+                }
+                x += count;
+            }
+        }
+    }
+
+    // The position of each pixel in the encoded data isn't kept, so the error points at its start
+    for &index in indexes.iter() {
+        match palette.get(index as usize) {
+            Some(&px) => data.push(px),
+            None => {
+                return Err(BmpError::new(
+                    InvalidPaletteIndex,
+                    "A pixel references a color outside of the palette",
+                )
+                .at(offset))
+            }
         }
     }
-    Ok(data)
+    Ok(())
 }
 
 fn read_pixels(
@@ -237,60 +690,494 @@ fn read_pixels(
     width: u32,
     height: u32,
     offset: u32,
-) -> BmpResult<Vec<Pixel>> {
-    let mut data = Vec::with_capacity((height * width) as usize);
-    // read pixels until padding
-    let mut px = [0; 3];
-    for y in 0..height {
-        for x in 0..width {
-            let lr = (y * width + x) as usize;
-            px.copy_from_slice(&bmp_data[offset as usize + lr * 4 .. offset as usize + (lr + 1) * 4 - 1]);
-            data.push(px!(px[2], px[1], px[0]));
-        }
+    data: &mut Vec<Pixel>,
+) -> BmpResult<()> {
+    // Each row is padded to a multiple of 4 bytes
+    // Computed in u64, so the row size of a very wide image can't overflow
+    let row_size = ((24 * width as u64 + 31) / 32 * 4) as usize;
+    for y in 0..height as usize {
+        let start = offset as usize + y * row_size;
+        let row = &bmp_data[start..start + width as usize * 3];
+        data.extend(row.chunks_exact(3).map(|px| px!(px[2], px[1], px[0])));
     }
-    Ok(data)
+    Ok(())
 }
 
-const BITS: usize = 8;
+// Returns the red, green, and blue masks used to decode a 16 or 32-bit image
+fn bitfield_masks(dh: &BmpDibHeader) -> [u32; 3] {
+    match (CompressionType::from_u32(dh.compress_type), dh.bits_per_pixel) {
+        (CompressionType::BitfieldsEncoding, _) => [dh.red_mask, dh.green_mask, dh.blue_mask],
+        // Uncompressed images use 5 bits per channel for 16-bit, and 8 bits for 32-bit images
+        (_, 16) => [0x7C00, 0x03E0, 0x001F],
+        _ => [0x00FF_0000, 0x0000_FF00, 0x0000_00FF],
+    }
+}
 
-#[derive(Debug)]
-struct BitIndex<'a> {
-    size: usize,
-    nbits: usize,
-    bits_left: usize,
-    mask: u8,
-    bytes: &'a [u8],
-    index: usize,
+// Extracts the channel selected by mask from a pixel and scales it to 8 bits
+fn masked_channel(word: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    let value = ((word & mask) >> shift) as u64;
+    ((value * 255 + max / 2) / max) as u8
 }
 
-fn bit_index<'a>(bytes: &'a [u8], nbits: usize, size: usize) -> BitIndex {
-    let bits_left = BITS - nbits;
-    BitIndex {
-        size,
-        nbits,
-        bits_left,
-        mask: (!0 as u8 >> bits_left),
-        bytes,
-        index: 0,
+fn read_pixels_bitfields(
+    bmp_data: &[u8],
+    width: u32,
+    height: u32,
+    offset: u32,
+    bpp: u16,
+    masks: [u32; 3],
+    data: &mut Vec<Pixel>,
+) -> BmpResult<()> {
+    let bytes_per_pixel = (bpp / 8) as usize;
+    // Each row is padded to a multiple of 4 bytes
+    let row_size = ((bpp as u64 * width as u64 + 31) / 32 * 4) as usize;
+    for y in 0..height as usize {
+        let start = offset as usize + y * row_size;
+        let row = &bmp_data[start..start + width as usize * bytes_per_pixel];
+        data.extend(row.chunks(bytes_per_pixel).map(|bytes| {
+            let word = match bytes_per_pixel {
+                2 => u16_from_slice(bytes) as u32,
+                _ => u32_from_slice(bytes),
+            };
+            px!(
+                masked_channel(word, masks[0]),
+                masked_channel(word, masks[1]),
+                masked_channel(word, masks[2])
+            )
+        }));
     }
+    Ok(())
 }
 
-impl<'a> Iterator for BitIndex<'a> {
-    type Item = usize;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consts::{BLACK, BLUE, RED, WHITE};
+    use tests::indexed_bmp;
+
+    // Builds a 32-bit BITFIELDS file with a 56-byte header, the words are given top-down
+    fn bitfields_bmp(width: u32, height: u32, masks: [u32; 4], words: &[u32]) -> Vec<u8> {
+        let data_size = 4 * width * height;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"BM");
+        bytes.extend_from_slice(&(70 + data_size).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&70u32.to_le_bytes());
+        bytes.extend_from_slice(&56u32.to_le_bytes());
+        bytes.extend_from_slice(&(width as i32).to_le_bytes());
+        bytes.extend_from_slice(&(height as i32).to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&32u16.to_le_bytes());
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&data_size.to_le_bytes());
+        bytes.extend_from_slice(&[0; 16]);
+        for mask in masks.iter() {
+            bytes.extend_from_slice(&mask.to_le_bytes());
+        }
+        for row in words.chunks(width as usize).rev() {
+            for word in row {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    // Turns a file with a 40-byte header into one with a larger header, with the given masks
+    fn with_masks(bmp: &[u8], masks: &[u32]) -> Vec<u8> {
+        let extra = 4 * masks.len() as u32;
+        let mut bytes = bmp[..54].to_vec();
+        bytes[2..6].copy_from_slice(&(u32_from_slice(&bmp[2..6]) + extra).to_le_bytes());
+        bytes[10..14].copy_from_slice(&(u32_from_slice(&bmp[10..14]) + extra).to_le_bytes());
+        bytes[14..18].copy_from_slice(&(40 + extra).to_le_bytes());
+        for mask in masks {
+            bytes.extend_from_slice(&mask.to_le_bytes());
+        }
+        bytes.extend_from_slice(&bmp[54..]);
+        bytes
+    }
+
+    #[test]
+    fn reject_images_over_the_pixel_limit() {
+        let bmp = Image::new(4, 3).to_bytes();
+        assert_eq!(12, decode_image_with_limit(&bmp, 12).unwrap().data.len());
+        assert_eq!(ImageTooLarge, decode_image_with_limit(&bmp, 11).unwrap_err().kind);
+
+        // A header claiming 30000 x 30000 pixels is rejected before reading any of them
+        let mut huge = bmp.clone();
+        huge[18..22].copy_from_slice(&30_000i32.to_le_bytes());
+        huge[22..26].copy_from_slice(&30_000i32.to_le_bytes());
+        assert_eq!(ImageTooLarge, decode_image(&huge).unwrap_err().kind);
+    }
+
+    #[test]
+    fn reject_indexes_outside_of_the_palette() {
+        let bmp = indexed_bmp(3, 1, 8, &[BLACK, RED], &[0, 1, 2]);
+        assert_eq!(InvalidPaletteIndex, decode_image(&bmp).unwrap_err().kind);
+        let bmp = indexed_bmp(2, 1, 4, &[BLACK, RED, BLUE], &[2, 15]);
+        assert_eq!(InvalidPaletteIndex, decode_image(&bmp).unwrap_err().kind);
+    }
+
+    #[test]
+    fn reject_inconsistent_palette_sizes() {
+        let bmp = indexed_bmp(2, 1, 1, &[BLACK, RED], &[0, 1]);
+        let with_num_colors = |num_colors: u32| {
+            let mut bytes = bmp.clone();
+            bytes[46..50].copy_from_slice(&num_colors.to_le_bytes());
+            bytes
+        };
+        for &num_colors in [3, 4_000_000_000].iter() {
+            let err = decode_image(&with_num_colors(num_colors)).unwrap_err();
+            assert_eq!(InvalidPalette, err.kind, "for {}", num_colors);
+        }
+
+        // 256 colors fit an 8-bit image, but not in this file
+        let mut bmp = indexed_bmp(2, 1, 8, &[BLACK, RED], &[0, 1]);
+        bmp[46..50].copy_from_slice(&256u32.to_le_bytes());
+        assert_eq!(InvalidPalette, decode_image(&bmp).unwrap_err().kind);
+    }
+
+    // Replaces the pixels of an uncompressed indexed file with a run-length encoded `stream`
+    fn with_rle(bmp: &[u8], stream: &[u8]) -> Vec<u8> {
+        let pixel_offset = u32_from_slice(&bmp[10..14]);
+        let bpp = u16_from_slice(&bmp[28..30]);
+        let mut bytes = bmp[..pixel_offset as usize].to_vec();
+        bytes[2..6].copy_from_slice(&(pixel_offset + stream.len() as u32).to_le_bytes());
+        bytes[30..34].copy_from_slice(&(if bpp == 8 { 1u32 } else { 2 }).to_le_bytes());
+        bytes[34..38].copy_from_slice(&(stream.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(stream);
+        bytes
+    }
+
+    #[test]
+    fn decode_rle8_with_escapes() {
+        let bmp = indexed_bmp(4, 3, 8, &[BLACK, RED, BLUE], &[0; 12]);
+        let stream = [
+            // Bottom row: an absolute run, padded to 16 bits, then the end of the line
+            0, 3, 1, 2, 1, 0, 0, 0,
+            // Skip the middle row and the first pixel of the top row
+            0, 2, 1, 1,
+            // A run clipped at the end of the row, then the end of the bitmap
+            5, 2, 0, 1,
+        ];
+        let img = decode_image(&with_rle(&bmp, &stream)).unwrap();
+        let rows = [[BLACK, BLUE, BLUE, BLUE], [BLACK; 4], [RED, BLUE, RED, BLACK]];
+        for (x, y) in img.coordinates() {
+            assert_eq!(rows[y as usize][x as usize], img.get_pixel(x, y));
+        }
+
+        assert_eq!(Truncated, decode_image(&with_rle(&bmp, &stream[..12])).unwrap_err().kind);
+    }
+
+    #[test]
+    fn decode_rle4_runs_alternate_nibbles() {
+        let bmp = indexed_bmp(5, 1, 4, &[BLACK, RED, BLUE], &[0; 5]);
+        let img = decode_image(&with_rle(&bmp, &[5, 0x12, 0, 1])).unwrap();
+        let row: Vec<Pixel> = (0..5).map(|x| img.get_pixel(x, 0)).collect();
+        assert_eq!(&[RED, BLUE, RED, BLUE, RED][..], &row[..]);
 
-    fn next(&mut self) -> Option<usize> {
-        let n = self.index / BITS;
-        let offset = self.bits_left - self.index % BITS;
+        // RLE4 can't be used with 8 bits per pixel
+        let mut bytes = with_rle(&indexed_bmp(5, 1, 8, &[BLACK], &[0; 5]), &[5, 0, 0, 1]);
+        bytes[30] = 2;
+        assert_eq!(UnsupportedCompressionType, decode_image(&bytes).unwrap_err().kind);
+    }
+
+    #[test]
+    fn errors_report_their_offset() {
+        let bmp = Image::new(4, 4).to_bytes();
+        for &len in [0, 10, 30, 60, bmp.len() - 1].iter() {
+            let err = decode_image(&bmp[..len]).unwrap_err();
+            assert_eq!(Truncated, err.kind, "for {} bytes", len);
+            assert_eq!(Some(len), err.offset);
+        }
+        // The last row doesn't need its padding
+        assert!(decode_image(&indexed_bmp(3, 2, 8, &[BLACK], &[0; 6])[..65]).is_ok());
+
+        let mut wrong_bpp = bmp.clone();
+        wrong_bpp[28] = 2;
+        assert_eq!(Some(28), decode_image(&wrong_bpp).unwrap_err().offset);
+        let bmp = indexed_bmp(3, 1, 8, &[BLACK, RED], &[0, 1, 2]);
+        let err = decode_image(&bmp).unwrap_err();
+        assert_eq!(Some(u32_from_slice(&bmp[10..14]) as usize + 2), err.offset);
+    }
+
+    #[test]
+    fn display_error_kind_and_details() {
+        let err = decode_image(b"BM").unwrap_err();
+        assert_eq!("Truncated file: The file ends too early", alloc::format!("{}", err));
+        assert_eq!(
+            "Invalid palette: Too many colors",
+            alloc::format!("{}", BmpError::new(InvalidPalette, "Too many colors").at(46))
+        );
+    }
+
+    #[test]
+    fn decode_without_a_data_size() {
+        let mut img = Image::new(5, 3);
+        img.set_pixel(1, 2, RED);
+        img.set_pixel(4, 0, WHITE);
+        for mut bytes in alloc::vec![img.to_bytes(), img.to_bytes_indexed(4).unwrap()] {
+            let data_size = u32_from_slice(&bytes[34..38]);
+            bytes[34..38].copy_from_slice(&[0; 4]);
+            let decoded = decode_image(&bytes).unwrap();
+            assert!(img.eq_pixels(&decoded));
+            // The size is recomputed, so the image is encoded the same way again
+            assert_eq!(data_size, decoded.dib_header.data_size);
+        }
+    }
 
-        self.index += self.nbits;
+    #[test]
+    fn reject_degenerate_dimensions() {
+        let bmp = Image::new(2, 2).to_bytes();
+        let with_dimensions = |width: i32, height: i32| {
+            let mut bytes = bmp.clone();
+            bytes[18..22].copy_from_slice(&width.to_le_bytes());
+            bytes[22..26].copy_from_slice(&height.to_le_bytes());
+            bytes
+        };
 
-        if self.size == 0 {
-            None
-        } else {
-            self.size -= 1;
-            self.bytes.get(n).map(|&block| {
-                ((block & self.mask << offset) >> offset) as usize
-            })
+        let invalid = [(0, 2), (-2, 2), (2, 0), (2, i32::MIN), (i32::MAX, i32::MAX)];
+        for &(width, height) in invalid.iter() {
+            let err = read_bmp_dib_header(&with_dimensions(width, height)).unwrap_err();
+            assert_eq!(InvalidDimensions, err.kind, "for {}x{}", width, height);
         }
+        assert!(read_bmp_dib_header(&with_dimensions(2, -2)).is_ok());
     }
-}
\ No newline at end of file
+
+    // Inserts `gap` junk bytes before the pixel data, as a v5 file may do for an ICC profile
+    fn with_gap(bmp: &[u8], gap: usize) -> Vec<u8> {
+        let pixel_offset = u32_from_slice(&bmp[10..14]);
+        let mut bytes = bmp[..pixel_offset as usize].to_vec();
+        bytes[2..6].copy_from_slice(&(u32_from_slice(&bmp[2..6]) + gap as u32).to_le_bytes());
+        bytes[10..14].copy_from_slice(&(pixel_offset + gap as u32).to_le_bytes());
+        bytes.extend(core::iter::repeat(0xAB).take(gap));
+        bytes.extend_from_slice(&bmp[pixel_offset as usize..]);
+        bytes
+    }
+
+    #[test]
+    fn decode_with_a_gap_before_the_pixels() {
+        // The palette follows the header, while the pixels start at `pixel_offset`
+        let indexed = indexed_bmp(3, 2, 8, &[BLACK, RED, BLUE], &[0, 1, 2, 2, 1, 0]);
+        let img = decode_image(&with_gap(&indexed, 9)).unwrap();
+        assert_eq!(&[BLACK, RED, BLUE][..], img.palette().unwrap());
+        assert_eq!(decode_image(&indexed).unwrap().data, img.data);
+
+        let mut rgb = Image::new(3, 2);
+        rgb.set_pixel(2, 1, WHITE);
+        assert_eq!(rgb.data, decode_image(&with_gap(&rgb.to_bytes(), 16)).unwrap().data);
+    }
+
+    #[test]
+    fn decode_56_byte_header() {
+        let bmp = indexed_bmp(3, 2, 8, &[BLACK, RED], &[0, 1, 0, 1, 1, 0]);
+        let bytes = with_masks(&bmp, &[0xFF0000, 0xFF00, 0xFF, 0xFF000000]);
+
+        let dib_header = read_bmp_dib_header(&bytes).unwrap();
+        assert_eq!(Some(BmpVersion::ThreeRgbaMasks), BmpVersion::from_dib_header(&dib_header));
+        assert_eq!(0xFF0000, dib_header.red_mask);
+        assert_eq!(0xFF00, dib_header.green_mask);
+        assert_eq!(0xFF, dib_header.blue_mask);
+        assert_eq!(0xFF000000, dib_header.alpha_mask);
+
+        let img = decode_image(&bytes).unwrap();
+        assert_eq!(&[BLACK, RED][..], img.palette().unwrap());
+        assert_eq!(BLACK, img.get_pixel(0, 0));
+        assert_eq!(RED, img.get_pixel(1, 0));
+        assert_eq!(RED, img.get_pixel(0, 1));
+        assert_eq!(BLACK, img.get_pixel(2, 1));
+    }
+
+    #[test]
+    fn decode_52_byte_header() {
+        let bmp = indexed_bmp(2, 2, 1, &[BLACK, RED], &[1, 0, 0, 1]);
+        let bytes = with_masks(&bmp, &[0xFF0000, 0xFF00, 0xFF]);
+
+        let dib_header = read_bmp_dib_header(&bytes).unwrap();
+        assert_eq!(Some(BmpVersion::ThreeRgbMasks), BmpVersion::from_dib_header(&dib_header));
+        assert_eq!(0, dib_header.alpha_mask);
+
+        let img = decode_image(&bytes).unwrap();
+        assert_eq!(RED, img.get_pixel(0, 0));
+        assert_eq!(BLACK, img.get_pixel(1, 0));
+        assert_eq!(RED, img.get_pixel(1, 1));
+    }
+
+    #[test]
+    fn decode_into_matches_decode_image() {
+        let mut img = Image::new(5, 3);
+        img.fill_rect(1, 0, 3, 2, RED);
+        img.set_pixel(4, 2, WHITE);
+
+        let mut buf = Vec::new();
+        let files = [
+            img.to_bytes(),
+            img.to_bytes_16(Bpp16Format::Rgb565),
+            img.to_bytes_indexed(4).unwrap(),
+        ];
+        for bytes in files.iter() {
+            assert_eq!((5, 3), decode_into(bytes, &mut buf).unwrap());
+            assert_eq!(decode_image(bytes).unwrap().data, buf);
+        }
+    }
+
+    #[test]
+    fn decode_565_image() {
+        let mut img = Image::new(3, 3);
+        img.set_pixel(0, 0, RED);
+        img.set_pixel(1, 0, px!(0, 255, 0));
+        img.set_pixel(2, 1, BLUE);
+        img.set_pixel(1, 2, WHITE);
+
+        for format in [Bpp16Format::Rgb565, Bpp16Format::Rgb555].iter() {
+            let decoded = decode_image(&img.to_bytes_16(*format)).unwrap();
+            assert_eq!((3, 3), (decoded.get_width(), decoded.get_height()));
+            for (x, y) in img.coordinates() {
+                assert_eq!(img.get_pixel(x, y), decoded.get_pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn decode_565_scales_channels_to_8_bits() {
+        let mut img = Image::new(1, 1);
+        img.set_pixel(0, 0, px!(0x84, 0x82, 0x84));
+        let decoded = decode_image(&img.to_bytes_16(Bpp16Format::Rgb565)).unwrap();
+        // 16 of 31 and 32 of 63
+        assert_eq!(px!(132, 130, 132), decoded.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn decode_8888_image() {
+        let masks = [0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000];
+        let words = [0xFFFF_0000, 0xFF00_FF00, 0xFF12_3456, 0x0000_00FF];
+        let img = decode_image(&bitfields_bmp(2, 2, masks, &words)).unwrap();
+        assert_eq!(RED, img.get_pixel(0, 0));
+        assert_eq!(px!(0, 255, 0), img.get_pixel(1, 0));
+        assert_eq!(px!(0x12, 0x34, 0x56), img.get_pixel(0, 1));
+        assert_eq!(BLUE, img.get_pixel(1, 1));
+    }
+
+    #[test]
+    fn decode_bitfields_with_arbitrary_masks() {
+        // RGBA byte order with a 4-bit green channel
+        let masks = [0xFF00_0000, 0x000F_0000, 0x0000_FF00, 0x0000_00FF];
+        let words = [0x1208_3400, 0xFF0F_FFFF];
+        let img = decode_image(&bitfields_bmp(2, 1, masks, &words)).unwrap();
+        assert_eq!(px!(0x12, 136, 0x34), img.get_pixel(0, 0));
+        assert_eq!(WHITE, img.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn decode_24_bit_rows_with_padding() {
+        // Two rows of two pixels, each padded from 6 to 8 bytes
+        let mut bytes = indexed_bmp(2, 2, 8, &[BLACK], &[0; 4])[..54].to_vec();
+        bytes[2..6].copy_from_slice(&70u32.to_le_bytes());
+        bytes[10..14].copy_from_slice(&54u32.to_le_bytes());
+        bytes[28..30].copy_from_slice(&24u16.to_le_bytes());
+        bytes[46..50].copy_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0, 255, 255, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&[255, 255, 255, 0, 0, 0, 0, 0]);
+
+        let img = decode_image(&bytes).unwrap();
+        assert_eq!(WHITE, img.get_pixel(0, 0));
+        assert_eq!(BLACK, img.get_pixel(1, 0));
+        assert_eq!(RED, img.get_pixel(0, 1));
+        assert_eq!(BLUE, img.get_pixel(1, 1));
+    }
+
+    #[test]
+    fn decode_from_iter_matches_decode_image() {
+        for bmp in fuzz_corpus().iter() {
+            let streamed = decode_from_iter(bmp.iter().cloned()).unwrap();
+            assert_eq!(decode_image(bmp).unwrap(), streamed);
+            for len in 0..bmp.len() {
+                let truncated = &bmp[..len];
+                if decode_image(truncated).is_err() {
+                    assert!(decode_from_iter(truncated.iter().cloned()).is_err());
+                }
+            }
+        }
+
+        // Only the pixels up to the end of the last row are pulled
+        let bytes = Image::new(4, 2).to_bytes();
+        let mut iter = bytes.iter().cloned().chain(core::iter::repeat(0xFF));
+        assert!(decode_from_iter(iter.by_ref()).is_ok());
+        assert_eq!(Some(0xFF), iter.next());
+
+        let err = decode_from_iter(bytes[..60].iter().cloned()).unwrap_err();
+        assert_eq!(Some(60), err.offset);
+        assert_eq!(Truncated, err.kind);
+    }
+
+    // A xorshift generator, so the fuzzing corpus is the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn fuzz_corpus() -> Vec<Vec<u8>> {
+        let mut img = Image::new(5, 3);
+        img.fill_rect(1, 0, 3, 2, RED);
+        img.set_pixel(4, 2, WHITE);
+        let masks = [0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000];
+        let indexed = indexed_bmp(3, 2, 4, &[BLACK, RED, BLUE], &[0, 1, 2, 2, 1, 0]);
+        alloc::vec![
+            img.to_bytes(),
+            img.to_bytes_16(Bpp16Format::Rgb565),
+            img.to_bytes_indexed(2).unwrap(),
+            img.to_bytes_rle8().unwrap(),
+            img.to_bytes_rle4().unwrap(),
+            img.to_bytes_with_thumbnail(&Image::new(1, 1)).unwrap(),
+            bitfields_bmp(2, 2, masks, &[0xFFFF_0000, 0, 0xFF12_3456, 0x0000_00FF]),
+            with_masks(&indexed, &[0xFF0000, 0xFF00, 0xFF]),
+            with_gap(&indexed, 7),
+        ]
+    }
+
+    // Any panic fails the test, so it's enough to decode each buffer and ignore the result
+    #[test]
+    fn decoding_never_panics() {
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+        for bmp in fuzz_corpus().iter() {
+            for len in 0..bmp.len() {
+                let _ = decode_image(&bmp[..len]);
+            }
+            for _ in 0..2000 {
+                // Mostly corrupt the headers, where a single byte changes how the rest is read
+                let mut bytes = bmp.clone();
+                for _ in 0..1 + rng.below(4) {
+                    let range = if rng.below(4) == 0 { bytes.len() } else { 80 };
+                    let i = rng.below(range.min(bytes.len()));
+                    bytes[i] = rng.next() as u8;
+                }
+                let len = bytes.len() - rng.below(8).min(bytes.len());
+                let _ = decode_image(&bytes[..len]);
+                let _ = decode_from_iter(bytes[..len].iter().cloned());
+                let _ = decode_thumbnail(&bytes);
+            }
+        }
+        for _ in 0..2000 {
+            let len = rng.below(256);
+            let mut bytes: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+            if len >= 2 {
+                bytes[..2].copy_from_slice(b"BM");
+            }
+            let _ = decode_image(&bytes);
+        }
+    }
+}
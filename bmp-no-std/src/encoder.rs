@@ -0,0 +1,647 @@
+// The BmpHeader always has a size of 14 bytes
+const BMP_HEADER_SIZE: u32 = 14;
+// The encoder always writes a BITMAPINFOHEADER, which has a size of 40 bytes
+const DIB_HEADER_SIZE: u32 = 40;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+// Import structs/functions defined in lib.rs
+use super::*;
+
+/// The pixel layouts that can be used when encoding an image with 16 bits per pixel.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Bpp16Format {
+    /// Five bits per channel, the top bit of every pixel is unused.
+    Rgb555,
+    /// Five bits for red and blue, and six bits for green. Written with BITFIELDS compression.
+    Rgb565,
+}
+
+impl Bpp16Format {
+    fn masks(&self) -> [u32; 3] {
+        match *self {
+            Bpp16Format::Rgb555 => [0x7C00, 0x03E0, 0x001F],
+            Bpp16Format::Rgb565 => [0xF800, 0x07E0, 0x001F],
+        }
+    }
+
+    fn pack(&self, px: Pixel) -> u16 {
+        let (r, g, b) = (px.r as u16, px.g as u16, px.b as u16);
+        match *self {
+            Bpp16Format::Rgb555 => ((r >> 3) << 10) | ((g >> 3) << 5) | (b >> 3),
+            Bpp16Format::Rgb565 => ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3),
+        }
+    }
+}
+
+/// Returns the size in bytes of one row of pixels, including the padding up to 4 bytes.
+fn row_size(bpp: u16, width: u32) -> u32 {
+    (bpp as u32 * width + 31) / 32 * 4
+}
+
+pub fn encode_image(image: &Image) -> Vec<u8> {
+    let row_size = row_size(24, image.width);
+    let pixel_offset = BMP_HEADER_SIZE + DIB_HEADER_SIZE;
+    let data_size = row_size * image.height;
+
+    let header = BmpHeader::new(pixel_offset, data_size);
+    let dib_header = BmpDibHeader {
+        header_size: DIB_HEADER_SIZE,
+        bits_per_pixel: 24,
+        compress_type: 0,
+        data_size,
+        num_colors: 0,
+        num_imp_colors: 0,
+        ..image.dib_header.clone()
+    };
+
+    let mut bmp_data = Vec::with_capacity(header.file_size as usize);
+    write_bmp_header(&mut bmp_data, &header);
+    write_bmp_dib_header(&mut bmp_data, &dib_header);
+
+    let padding = (row_size - image.width * 3) as usize;
+    // Pixel rows are stored bottom-up in the same order as the image data
+    for row in image.data.chunks(image.width.max(1) as usize) {
+        for px in row {
+            bmp_data.extend_from_slice(&[px.b, px.g, px.r]);
+        }
+        bmp_data.extend((0..padding).map(|_| 0u8));
+    }
+
+    bmp_data
+}
+
+pub fn encode_image_with_thumbnail(image: &Image, thumb: &Image) -> BmpResult<Vec<u8>> {
+    let mut bmp_data = encode_image(image);
+    let thumb_data = encode_image(thumb);
+    let trailer = thumbnail_trailer(bmp_data.len(), thumb_data.len())?;
+    bmp_data.reserve_exact(thumb_data.len() + THUMBNAIL_TRAILER_SIZE);
+    bmp_data.extend_from_slice(&thumb_data);
+    bmp_data.extend_from_slice(&trailer);
+    Ok(bmp_data)
+}
+
+// Returns the trailer recording the `offset` and `size` of a thumbnail, which are stored in 32 bits
+fn thumbnail_trailer(offset: usize, size: usize) -> BmpResult<Vec<u8>> {
+    if offset > u32::MAX as usize || size > u32::MAX as usize {
+        return Err(BmpError::new(
+            BmpErrorKind::ImageTooLarge,
+            "The image and its thumbnail must be smaller than 4 GiB each",
+        ));
+    }
+    let mut trailer = Vec::with_capacity(THUMBNAIL_TRAILER_SIZE);
+    trailer.extend_from_slice(THUMBNAIL_MAGIC);
+    trailer.extend_from_slice(&(offset as u32).to_le_bytes());
+    trailer.extend_from_slice(&(size as u32).to_le_bytes());
+    Ok(trailer)
+}
+
+pub fn encode_image_16(image: &Image, format: Bpp16Format) -> Vec<u8> {
+    let row_size = row_size(16, image.width);
+    let masks_size = match format {
+        Bpp16Format::Rgb555 => 0,
+        Bpp16Format::Rgb565 => 12,
+    };
+    let pixel_offset = BMP_HEADER_SIZE + DIB_HEADER_SIZE + masks_size;
+    let data_size = row_size * image.height;
+
+    let header = BmpHeader::new(pixel_offset, data_size);
+    let dib_header = BmpDibHeader {
+        header_size: DIB_HEADER_SIZE,
+        bits_per_pixel: 16,
+        compress_type: if masks_size == 0 { 0 } else { 3 },
+        data_size,
+        // The pixels are stored directly, without a color table
+        num_colors: 0,
+        num_imp_colors: 0,
+        ..image.dib_header.clone()
+    };
+
+    let mut bmp_data = Vec::with_capacity(header.file_size as usize);
+    write_bmp_header(&mut bmp_data, &header);
+    write_bmp_dib_header(&mut bmp_data, &dib_header);
+    if masks_size != 0 {
+        for mask in format.masks().iter() {
+            bmp_data.extend_from_slice(&mask.to_le_bytes());
+        }
+    }
+
+    let padding = (row_size - image.width * 2) as usize;
+    // Pixel rows are stored bottom-up in the same order as the image data
+    for row in image.data.chunks(image.width.max(1) as usize) {
+        for px in row {
+            bmp_data.extend_from_slice(&format.pack(*px).to_le_bytes());
+        }
+        bmp_data.extend((0..padding).map(|_| 0u8));
+    }
+
+    bmp_data
+}
+
+pub fn encode_image_indexed(image: &Image, max_colors: u16) -> BmpResult<Vec<u8>> {
+    if max_colors == 0 || max_colors > 256 {
+        return Err(BmpError::new(
+            BmpErrorKind::InvalidPalette,
+            "An indexed image can have between 1 and 256 colors",
+        ));
+    }
+
+    let colors = median_cut(image, max_colors as usize);
+    let quantized: Vec<Pixel> = image
+        .data
+        .iter()
+        .map(|&px| match colors {
+            Some(ref colors) => colors[&u32::from(px)],
+            None => px,
+        })
+        .collect();
+
+    let (palette, indexes) = palette_indexes(&quantized);
+
+    let bpp: u16 = match palette.len() {
+        0..=2 => 1,
+        3..=16 => 4,
+        _ => 8,
+    };
+    let row_size = row_size(bpp, image.width);
+    let pixel_offset = BMP_HEADER_SIZE + DIB_HEADER_SIZE + 4 * palette.len() as u32;
+    let data_size = row_size * image.height;
+
+    let header = BmpHeader::new(pixel_offset, data_size);
+    let dib_header = BmpDibHeader {
+        header_size: DIB_HEADER_SIZE,
+        bits_per_pixel: bpp,
+        compress_type: 0,
+        data_size,
+        num_colors: palette.len() as u32,
+        num_imp_colors: 0,
+        ..image.dib_header.clone()
+    };
+
+    let mut bmp_data = Vec::with_capacity(header.file_size as usize);
+    write_bmp_header(&mut bmp_data, &header);
+    write_bmp_dib_header(&mut bmp_data, &dib_header);
+    for px in &palette {
+        bmp_data.extend_from_slice(&[px.b, px.g, px.r, 0]);
+    }
+
+    // Pack the indexes of every row, most significant bits first
+    let mut row = Vec::with_capacity(row_size as usize);
+    for row_indexes in indexes.chunks(image.width.max(1) as usize) {
+        row.clear();
+        row.resize(row_size as usize, 0);
+        for (x, &index) in row_indexes.iter().enumerate() {
+            let bit = x * bpp as usize;
+            row[bit / 8] |= index << (8 - bpp as usize - bit % 8);
+        }
+        bmp_data.extend_from_slice(&row);
+    }
+
+    Ok(bmp_data)
+}
+
+pub fn encode_image_rle(image: &Image, bpp: u16) -> BmpResult<Vec<u8>> {
+    if image.unique_colors() > 1 << bpp {
+        return Err(BmpError::new(
+            BmpErrorKind::InvalidPalette,
+            "The image has more colors than the bits per pixel can index",
+        ));
+    }
+    let (palette, indexes) = palette_indexes(&image.data);
+
+    // Every row but the last one ends with an end of line, and the last one ends the bitmap
+    let mut data = Vec::new();
+    for (y, row) in indexes.chunks(image.width.max(1) as usize).enumerate() {
+        if y > 0 {
+            data.extend_from_slice(&[0, 0]);
+        }
+        encode_rle_row(row, bpp, &mut data);
+    }
+    data.extend_from_slice(&[0, 1]);
+
+    let pixel_offset = BMP_HEADER_SIZE + DIB_HEADER_SIZE + 4 * palette.len() as u32;
+    let header = BmpHeader::new(pixel_offset, data.len() as u32);
+    let dib_header = BmpDibHeader {
+        header_size: DIB_HEADER_SIZE,
+        bits_per_pixel: bpp,
+        compress_type: if bpp == 8 { 1 } else { 2 },
+        data_size: data.len() as u32,
+        num_colors: palette.len() as u32,
+        num_imp_colors: 0,
+        ..image.dib_header.clone()
+    };
+
+    let mut bmp_data = Vec::with_capacity(header.file_size as usize);
+    write_bmp_header(&mut bmp_data, &header);
+    write_bmp_dib_header(&mut bmp_data, &dib_header);
+    for px in &palette {
+        bmp_data.extend_from_slice(&[px.b, px.g, px.r, 0]);
+    }
+    bmp_data.extend_from_slice(&data);
+    Ok(bmp_data)
+}
+
+// Returns the palette of the given pixels and their indexes in it. The palette is sorted by the
+// packed value of its colors, rather than by where they first appear in the image, so the same
+// pixels always yield the same bytes.
+fn palette_indexes(pixels: &[Pixel]) -> (Vec<Pixel>, Vec<u8>) {
+    let mut lookup: BTreeMap<u32, u8> = pixels.iter().map(|&px| (u32::from(px), 0)).collect();
+    for (index, value) in lookup.values_mut().enumerate() {
+        *value = index as u8;
+    }
+    let palette = lookup.keys().map(|&rgb| Pixel::from(rgb)).collect();
+    let indexes = pixels.iter().map(|&px| lookup[&u32::from(px)]).collect();
+    (palette, indexes)
+}
+
+// Appends the run-length encoding of a row of 4 or 8-bit indexes to `out`.
+//
+// A run of at least 3 equal indexes is always encoded as a run. The indexes between such runs are
+// written in absolute mode, which costs a 2-byte escape and a padding to 16 bits. Fewer than the 3
+// indexes absolute mode requires are written as runs of 1 or 2 instead, 2 bytes each.
+fn encode_rle_row(row: &[u8], bpp: u16, out: &mut Vec<u8>) {
+    let run_at = |i: usize| row[i..].iter().take(255).take_while(|&&index| index == row[i]).count();
+    // The byte repeated by a run, a 4-bit run alternates between the two nibbles
+    let run_byte = |index: u8| if bpp == 4 { index << 4 | index } else { index };
+
+    let mut i = 0;
+    while i < row.len() {
+        let run = run_at(i);
+        if run >= 3 {
+            out.extend_from_slice(&[run as u8, run_byte(row[i])]);
+            i += run;
+            continue;
+        }
+
+        let mut end = i;
+        while end < row.len() && end - i < 255 && run_at(end) < 3 {
+            end += 1;
+        }
+        let literal = &row[i..end];
+        if literal.len() >= 3 {
+            out.extend_from_slice(&[0, literal.len() as u8]);
+            let start = out.len();
+            if bpp == 8 {
+                out.extend_from_slice(literal);
+            } else {
+                let pack = |pair: &[u8]| pair[0] << 4 | pair.get(1).map_or(0, |&low| low);
+                out.extend(literal.chunks(2).map(pack));
+            }
+            if (out.len() - start) % 2 == 1 {
+                out.push(0);
+            }
+        } else {
+            let mut j = i;
+            while j < end {
+                let run = run_at(j).min(end - j);
+                out.extend_from_slice(&[run as u8, run_byte(row[j])]);
+                j += run;
+            }
+        }
+        i = end;
+    }
+}
+
+// Maps every color of the image to its replacement in a median-cut palette of at most
+// `max_colors` colors, or returns `None` if the image already has few enough colors.
+//
+// The colors start in a single box, and the box with the widest channel range is repeatedly split
+// at the pixel-weighted median of that channel. Each color is replaced by the average of its box.
+fn median_cut(image: &Image, max_colors: usize) -> Option<BTreeMap<u32, Pixel>> {
+    if image.has_at_most_colors(max_colors) {
+        return None;
+    }
+    let mut counts: BTreeMap<u32, u64> = BTreeMap::new();
+    for &px in &image.data {
+        *counts.entry(u32::from(px)).or_insert(0) += 1;
+    }
+    // The red, green and blue channels of a packed color are 0, 1 and 2
+    let channel = |rgb: u32, c: u32| (rgb >> (16 - 8 * c)) as u8;
+    // The range of the widest channel of a box and that channel, ties go to the lowest channel
+    let widest = |colors: &[(u32, u64)]| {
+        (0..3)
+            .map(|c| {
+                let min = colors.iter().map(|&(rgb, _)| channel(rgb, c)).min().unwrap();
+                let max = colors.iter().map(|&(rgb, _)| channel(rgb, c)).max().unwrap();
+                (max - min, c)
+            })
+            .max_by_key(|&(range, c)| (range, core::cmp::Reverse(c)))
+            .unwrap()
+    };
+
+    let mut boxes: Vec<Vec<(u32, u64)>> = alloc::vec![counts.into_iter().collect()];
+    while boxes.len() < max_colors {
+        let candidate = (0..boxes.len())
+            .map(|i| (widest(&boxes[i]), i))
+            .filter(|&((range, _), _)| range > 0)
+            .max_by_key(|&((range, _), i)| (range, core::cmp::Reverse(i)));
+        let ((_, c), i) = match candidate {
+            Some(candidate) => candidate,
+            None => break,
+        };
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_by_key(|&(rgb, _)| (channel(rgb, c), rgb));
+        let total: u64 = colors.iter().map(|&(_, count)| count).sum();
+        let mut seen = 0;
+        let mut split = colors
+            .iter()
+            .position(|&(_, count)| {
+                seen += count;
+                2 * seen >= total
+            })
+            .unwrap()
+            + 1;
+        // Both halves must keep at least one color
+        split = split.min(colors.len() - 1);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    let mut replacements = BTreeMap::new();
+    for colors in &boxes {
+        let total: u64 = colors.iter().map(|&(_, count)| count).sum();
+        let average = |c: u32| {
+            let sum: u64 = colors.iter().map(|&(rgb, count)| channel(rgb, c) as u64 * count).sum();
+            ((sum + total / 2) / total) as u8
+        };
+        let px = px!(average(0), average(1), average(2));
+        replacements.extend(colors.iter().map(|&(rgb, _)| (rgb, px)));
+    }
+    Some(replacements)
+}
+
+fn write_bmp_header(bmp_data: &mut Vec<u8>, header: &BmpHeader) {
+    bmp_data.extend_from_slice(b"BM");
+    bmp_data.extend_from_slice(&header.file_size.to_le_bytes());
+    bmp_data.extend_from_slice(&header.creator1.to_le_bytes());
+    bmp_data.extend_from_slice(&header.creator2.to_le_bytes());
+    bmp_data.extend_from_slice(&header.pixel_offset.to_le_bytes());
+}
+
+fn write_bmp_dib_header(bmp_data: &mut Vec<u8>, dib_header: &BmpDibHeader) {
+    bmp_data.extend_from_slice(&dib_header.header_size.to_le_bytes());
+    bmp_data.extend_from_slice(&dib_header.width.to_le_bytes());
+    bmp_data.extend_from_slice(&dib_header.height.to_le_bytes());
+    bmp_data.extend_from_slice(&dib_header.num_planes.to_le_bytes());
+    bmp_data.extend_from_slice(&dib_header.bits_per_pixel.to_le_bytes());
+    bmp_data.extend_from_slice(&dib_header.compress_type.to_le_bytes());
+    bmp_data.extend_from_slice(&dib_header.data_size.to_le_bytes());
+    bmp_data.extend_from_slice(&dib_header.hres.to_le_bytes());
+    bmp_data.extend_from_slice(&dib_header.vres.to_le_bytes());
+    bmp_data.extend_from_slice(&dib_header.num_colors.to_le_bytes());
+    bmp_data.extend_from_slice(&dib_header.num_imp_colors.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use decoder::{u16_from_slice, u32_from_slice};
+
+    #[test]
+    fn encode_rle_row_picks_runs_and_absolute_mode() {
+        let mut out = Vec::new();
+        encode_rle_row(&[5, 5, 5, 5, 1, 2, 3, 7, 7, 7], 8, &mut out);
+        assert_eq!(&[4, 5, 0, 3, 1, 2, 3, 0, 3, 7][..], &out[..]);
+
+        out.clear();
+        encode_rle_row(&[1, 2, 3, 4, 4, 4], 4, &mut out);
+        assert_eq!(&[0, 3, 0x12, 0x30, 3, 0x44][..], &out[..]);
+
+        // Too short for absolute mode, equal neighbors still share a run
+        out.clear();
+        encode_rle_row(&[1, 2, 9, 9, 9, 9, 4, 4], 8, &mut out);
+        assert_eq!(&[1, 1, 1, 2, 4, 9, 2, 4][..], &out[..]);
+    }
+
+    #[test]
+    fn rle_round_trip_of_a_striped_image() {
+        let mut img = Image::new(40, 12);
+        for y in 0..12 {
+            img.fill_rect(0, y, 40, 1, if y % 3 == 0 { consts::RED } else { consts::WHITE });
+        }
+        // Some noise for absolute mode
+        for x in 0..7 {
+            img.set_pixel(x * 5, 4, [consts::BLACK, consts::BLUE][x as usize % 2]);
+        }
+
+        let encodings = [(img.to_bytes_rle8().unwrap(), 8), (img.to_bytes_rle4().unwrap(), 4)];
+        for &(ref bytes, bpp) in encodings.iter() {
+            assert_eq!(bpp, u16_from_slice(&bytes[28..30]));
+            assert!(bytes.len() < img.to_bytes_indexed(256).unwrap().len());
+            assert!(img.eq_pixels(&from_bytes(&bytes).unwrap()));
+        }
+    }
+
+    #[test]
+    fn rle4_rejects_too_many_colors() {
+        let mut img = Image::new(17, 1);
+        img.map_pixels(|x, _, _| px!(x, 0, 0));
+        assert!(img.to_bytes_rle8().is_ok());
+        assert_eq!(BmpErrorKind::InvalidPalette, img.to_bytes_rle4().unwrap_err().kind);
+    }
+
+    #[test]
+    fn encode_24_bit_round_trip() {
+        let mut img = Image::new(3, 2);
+        img.set_pixel(0, 0, consts::RED);
+        img.set_pixel(2, 1, px!(1, 2, 3));
+        let bytes = img.to_bytes();
+
+        assert_eq!(bytes.len() as u32, u32_from_slice(&bytes[2..6]));
+        assert_eq!(54, u32_from_slice(&bytes[10..14]));
+        assert_eq!(24, u16_from_slice(&bytes[28..30]));
+        // Two rows of three pixels, each padded from 9 to 12 bytes
+        assert_eq!(54 + 24, bytes.len());
+
+        let decoded = from_bytes(&bytes).unwrap();
+        for (x, y) in img.coordinates() {
+            assert_eq!(img.get_pixel(x, y), decoded.get_pixel(x, y));
+        }
+
+        // Indexed images are written without their palette
+        let indexed = from_bytes(&img.to_bytes_indexed(16).unwrap()).unwrap();
+        assert_eq!(bytes, indexed.to_bytes());
+    }
+
+    #[test]
+    fn thumbnail_round_trip() {
+        let mut img = Image::new(64, 48);
+        img.fill_rect(0, 0, 32, 48, consts::RED);
+        let mut thumb = Image::new(16, 16);
+        thumb.draw_rect(0, 0, 16, 16, consts::WHITE);
+        let bytes = img.to_bytes_with_thumbnail(&thumb).unwrap();
+
+        // The main image is unchanged for any decoder
+        assert_eq!(&img.to_bytes()[..], &bytes[..img.to_bytes().len()]);
+        assert_eq!(img, from_bytes(&bytes).unwrap());
+
+        let extracted = extract_thumbnail(&bytes).unwrap().unwrap();
+        assert_eq!((16, 16), (extracted.get_width(), extracted.get_height()));
+        for (x, y) in thumb.coordinates() {
+            assert_eq!(thumb.get_pixel(x, y), extracted.get_pixel(x, y));
+        }
+
+        assert!(extract_thumbnail(&img.to_bytes()).unwrap().is_none());
+        // Any image can be embedded, even one larger than the main image
+        let bytes = thumb.to_bytes_with_thumbnail(&img).unwrap();
+        assert_eq!(img, extract_thumbnail(&bytes).unwrap().unwrap());
+    }
+
+    #[test]
+    fn thumbnail_trailer_rejects_offsets_past_4_gib() {
+        let max = u32::MAX as usize;
+        assert_eq!(&THUMBNAIL_MAGIC[..], &thumbnail_trailer(max, max).unwrap()[..4]);
+        let err = thumbnail_trailer(max + 1, 16).unwrap_err();
+        assert_eq!(BmpErrorKind::ImageTooLarge, err.kind);
+        assert!(thumbnail_trailer(54, max + 1).is_err());
+    }
+
+    #[test]
+    fn encode_565_writes_bitfields_header() {
+        let mut img = Image::new(3, 2);
+        img.set_pixel(0, 1, consts::WHITE);
+        let bytes = img.to_bytes_16(Bpp16Format::Rgb565);
+
+        assert_eq!(b"BM", &bytes[..2]);
+        assert_eq!(bytes.len() as u32, u32_from_slice(&bytes[2..6]));
+        assert_eq!(66, u32_from_slice(&bytes[10..14]));
+        assert_eq!(16, u16_from_slice(&bytes[28..30]));
+        assert_eq!(3, u32_from_slice(&bytes[30..34]));
+        assert_eq!(0xF800, u32_from_slice(&bytes[54..58]));
+        assert_eq!(0x07E0, u32_from_slice(&bytes[58..62]));
+        assert_eq!(0x001F, u32_from_slice(&bytes[62..66]));
+        // Two rows of three pixels, each padded from 6 to 8 bytes
+        assert_eq!(66 + 16, bytes.len());
+        // The bottom left pixel is stored first
+        assert_eq!(0xFFFF, u16_from_slice(&bytes[66..68]));
+        assert_eq!(0, u16_from_slice(&bytes[68..70]));
+    }
+
+    #[test]
+    fn encode_555_writes_uncompressed_header() {
+        let mut img = Image::new(2, 1);
+        img.set_pixel(1, 0, px!(255, 0, 8));
+        let bytes = img.to_bytes_16(Bpp16Format::Rgb555);
+
+        assert_eq!(54, u32_from_slice(&bytes[10..14]));
+        assert_eq!(0, u32_from_slice(&bytes[30..34]));
+        assert_eq!(54 + 4, bytes.len());
+        assert_eq!(0x7C01, u16_from_slice(&bytes[56..58]));
+    }
+
+    #[test]
+    fn encode_16_round_trips_within_the_quantization_tolerance() {
+        let mut img = Image::new(7, 5);
+        img.map_pixels(|x, y, _| px!(x * 37 + y * 11, x * 59 + y * 101, x * 13 + y * 47));
+        let formats = [(Bpp16Format::Rgb555, 8), (Bpp16Format::Rgb565, 4)];
+        for &(format, green_tolerance) in formats.iter() {
+            let decoded = from_bytes(&img.to_bytes_16(format)).unwrap();
+            for (x, y) in img.coordinates() {
+                let (a, b) = (img.get_pixel(x, y), decoded.get_pixel(x, y));
+                let error = |a: u8, b: u8| (a as i32 - b as i32).abs();
+                assert!(error(a.r, b.r) <= 8, "{:?} at ({}, {})", format, x, y);
+                assert!(error(a.g, b.g) <= green_tolerance, "{:?} at ({}, {})", format, x, y);
+                assert!(error(a.b, b.b) <= 8, "{:?} at ({}, {})", format, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_16_of_an_indexed_image_has_no_color_table() {
+        let mut img = Image::new(3, 2);
+        img.set_pixel(0, 0, consts::RED);
+        img.set_pixel(1, 1, consts::WHITE);
+        let indexed = from_bytes(&img.to_bytes_indexed(256).unwrap()).unwrap();
+        assert_eq!(3, indexed.dib_header.num_colors);
+
+        let bytes = indexed.to_bytes_16(Bpp16Format::Rgb565);
+        assert_eq!(0, u32_from_slice(&bytes[46..50]));
+        assert_eq!(0, u32_from_slice(&bytes[50..54]));
+        assert!(img.eq_pixels(&from_bytes(&bytes).unwrap()));
+    }
+
+    #[test]
+    fn indexed_round_trip_of_a_four_color_image() {
+        let mut img = Image::new(5, 3);
+        img.fill_rect(0, 0, 2, 3, consts::RED);
+        img.fill_rect(2, 0, 2, 2, consts::BLUE);
+        img.fill_rect(2, 2, 3, 1, consts::WHITE);
+        let bytes = img.to_bytes_indexed(256).unwrap();
+
+        // Four colors fit in a 4-bit palette
+        assert_eq!(4, u16_from_slice(&bytes[28..30]));
+        assert_eq!(4, u32_from_slice(&bytes[46..50]));
+        // Each row of five 4-bit indexes is padded from 3 to 4 bytes
+        assert_eq!(54 + 16 + 12, bytes.len());
+
+        let decoded = from_bytes(&bytes).unwrap();
+        assert_eq!(4, decoded.palette().unwrap().len());
+        for (x, y) in img.coordinates() {
+            assert_eq!(img.get_pixel(x, y), decoded.get_pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn indexed_encoding_quantizes_to_max_colors() {
+        let mut img = Image::new(16, 16);
+        for (x, y) in img.coordinates() {
+            img.set_pixel(x, y, px!(x * 16, y * 16, 128));
+        }
+        let decoded = from_bytes(&img.to_bytes_indexed(8).unwrap()).unwrap();
+        assert!(decoded.palette().unwrap().len() <= 8);
+        assert!(decoded.has_at_most_colors(8));
+    }
+
+    #[test]
+    fn indexed_encoding_keeps_a_gradient_with_few_colors() {
+        let mut img = Image::new(16, 16);
+        img.map_pixels(|x, y, _| {
+            let v = (x + 16 * y) as u8;
+            px!(v, v, v)
+        });
+        for &max_colors in [2, 4].iter() {
+            let decoded = from_bytes(&img.to_bytes_indexed(max_colors).unwrap()).unwrap();
+            let colors = decoded.palette().unwrap().len();
+            assert!(colors > 1 && colors <= max_colors as usize, "for {}", max_colors);
+
+            let max_error = img
+                .data
+                .iter()
+                .zip(&decoded.data)
+                .map(|(a, b)| (a.r as i32 - b.r as i32).abs())
+                .max()
+                .unwrap();
+            assert!(max_error <= 128 / max_colors as i32, "for {}: {}", max_colors, max_error);
+        }
+    }
+
+    #[test]
+    fn indexed_encoding_sorts_the_palette() {
+        let mut img = Image::new(4, 2);
+        img.fill_rect(0, 0, 2, 2, consts::WHITE);
+        img.set_pixel(3, 1, consts::RED);
+        img.set_pixel(2, 0, consts::BLUE);
+        let bytes = img.to_bytes_indexed(16).unwrap();
+        assert_eq!(bytes, img.to_bytes_indexed(16).unwrap());
+
+        let decoded = from_bytes(&bytes).unwrap();
+        assert_eq!(
+            &[consts::BLACK, consts::BLUE, consts::RED, consts::WHITE][..],
+            decoded.palette().unwrap()
+        );
+        // Re-encoding the decoded image gives the same file
+        assert_eq!(bytes, decoded.to_bytes_indexed(16).unwrap());
+        for (x, y) in img.coordinates() {
+            assert_eq!(img.get_pixel(x, y), decoded.get_pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn indexed_encoding_rejects_invalid_palette_sizes() {
+        let img = Image::new(2, 2);
+        assert!(img.to_bytes_indexed(0).is_err());
+        assert!(img.to_bytes_indexed(257).is_err());
+    }
+}
@@ -3,13 +3,31 @@
 #![no_std]
 // Expose decoder's public types, structs, and enums
 pub use decoder::{BmpError, BmpErrorKind, BmpResult};
+// Expose encoder's public types
+pub use encoder::Bpp16Format;
 
-/// Macro to generate a `Pixel` from `r`, `g` and `b` values.
+/// Macro to generate a `Pixel` from `r`, `g` and `b` values, or a gray `Pixel` from a single
+/// value used for all three channels.
+///
+/// # Example
+///
+/// ```
+/// #[macro_use]
+/// extern crate bmp;
+/// use bmp::Pixel;
+///
+/// fn main() {
+///     assert_eq!(px!(128, 128, 128), px!(128));
+/// }
+/// ```
 #[macro_export]
 macro_rules! px {
     ($r:expr, $g:expr, $b:expr) => {
         Pixel { r: $r as u8, g: $g as u8, b: $b as u8 }
-    }
+    };
+    ($v:expr) => {
+        Pixel { r: $v as u8, g: $v as u8, b: $v as u8 }
+    };
 }
 
 macro_rules! file_size {
@@ -21,18 +39,45 @@ macro_rules! file_size {
     }}
 }
 
+// Marks the trailer recording where an embedded thumbnail is stored, followed by its offset and
+// size as little-endian u32s
+const THUMBNAIL_MAGIC: &[u8; 4] = b"BTHM";
+const THUMBNAIL_TRAILER_SIZE: usize = 12;
+
+/// The largest number of pixels `from_bytes` decodes, 8192 x 8192. Use `from_bytes_with_limit`
+/// to decode larger images, or to lower the limit on memory or cycle constrained targets.
+pub const DEFAULT_MAX_PIXELS: usize = 1 << 26;
+
 /// Common color constants accessible by names.
 pub mod consts;
 
 mod decoder;
+mod encoder;
+#[cfg(feature = "serde")]
+mod serde_impl;
 
 extern crate alloc;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "std")]
+extern crate std;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_test;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::iter::Rev;
+use core::ops::{Add, Mul, Sub};
+use core::slice::{Chunks, ChunksMut};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// The pixel data used in the `Image`.
 ///
 /// It has three values for the `red`, `blue` and `green` color channels, respectively.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Pixel {
     pub r: u8,
     pub g: u8,
@@ -44,6 +89,270 @@ impl Pixel {
     pub fn new(r: u8, g: u8, b: u8) -> Pixel {
         Pixel { r: r, g: g, b: b }
     }
+
+    /// Returns the luma of the pixel, using the integer ITU-R BT.601 weights.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// assert_eq!(255, bmp::consts::WHITE.luminance());
+    /// assert_eq!(76, bmp::consts::RED.luminance());
+    /// ```
+    pub fn luminance(&self) -> u8 {
+        ((299 * self.r as u32 + 587 * self.g as u32 + 114 * self.b as u32 + 500) / 1000) as u8
+    }
+
+    /// Returns the squared Euclidean distance between two pixels in RGB space.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bmp::Pixel;
+    ///
+    /// assert_eq!(3 * 255 * 255, bmp::consts::BLACK.distance_sq(bmp::consts::WHITE));
+    /// assert_eq!(14, Pixel::new(1, 2, 3).distance_sq(Pixel::new(2, 4, 6)));
+    /// ```
+    pub fn distance_sq(self, other: Pixel) -> u32 {
+        let d = |a: u8, b: u8| (a as i32 - b as i32) * (a as i32 - b as i32);
+        (d(self.r, other.r) + d(self.g, other.g) + d(self.b, other.b)) as u32
+    }
+
+    /// Linearly interpolates between this pixel at `t = 0` and `other` at `t = 1`, rounding
+    /// every channel. `t` is clamped to `0..=1`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bmp::{consts, Pixel};
+    ///
+    /// assert_eq!(Pixel::new(128, 0, 128), consts::RED.lerp(consts::BLUE, 0.5));
+    /// ```
+    pub fn lerp(self, other: Pixel, t: f32) -> Pixel {
+        let t = if t > 0.0 { t.min(1.0) } else { 0.0 };
+        let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t + 0.5) as u8;
+        px!(channel(self.r, other.r), channel(self.g, other.g), channel(self.b, other.b))
+    }
+
+    /// Blends `over` on top of this pixel with an opacity of `alpha`, where 0 keeps this pixel
+    /// and 255 replaces it with `over`. Only integer arithmetic is used.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bmp::{consts, Pixel};
+    ///
+    /// assert_eq!(Pixel::new(128, 128, 128), consts::BLACK.blend(consts::WHITE, 128));
+    /// ```
+    pub fn blend(self, over: Pixel, alpha: u8) -> Pixel {
+        let (a, inv) = (alpha as u32, 255 - alpha as u32);
+        let channel = |below: u8, above: u8| {
+            ((above as u32 * a + below as u32 * inv + 127) / 255) as u8
+        };
+        px!(channel(self.r, over.r), channel(self.g, over.g), channel(self.b, over.b))
+    }
+}
+
+/// Adds the channels of two pixels, saturating at 255.
+///
+/// # Example
+///
+/// ```
+/// use bmp::Pixel;
+///
+/// assert_eq!(Pixel::new(255, 30, 3), Pixel::new(200, 20, 1) + Pixel::new(100, 10, 2));
+/// ```
+impl Add for Pixel {
+    type Output = Pixel;
+
+    fn add(self, other: Pixel) -> Pixel {
+        Pixel {
+            r: self.r.saturating_add(other.r),
+            g: self.g.saturating_add(other.g),
+            b: self.b.saturating_add(other.b),
+        }
+    }
+}
+
+/// Subtracts the channels of two pixels, saturating at 0.
+impl Sub for Pixel {
+    type Output = Pixel;
+
+    fn sub(self, other: Pixel) -> Pixel {
+        Pixel {
+            r: self.r.saturating_sub(other.r),
+            g: self.g.saturating_sub(other.g),
+            b: self.b.saturating_sub(other.b),
+        }
+    }
+}
+
+/// Multiplies every channel by an integer factor, saturating at 255.
+impl Mul<u8> for Pixel {
+    type Output = Pixel;
+
+    fn mul(self, factor: u8) -> Pixel {
+        Pixel {
+            r: self.r.saturating_mul(factor),
+            g: self.g.saturating_mul(factor),
+            b: self.b.saturating_mul(factor),
+        }
+    }
+}
+
+/// Multiplies every channel by a factor and rounds the result to the nearest value within
+/// `0..=255`.
+///
+/// # Example
+///
+/// ```
+/// use bmp::Pixel;
+///
+/// assert_eq!(Pixel::new(50, 128, 128), Pixel::new(100, 255, 255) * 0.5);
+/// ```
+impl Mul<f32> for Pixel {
+    type Output = Pixel;
+
+    fn mul(self, factor: f32) -> Pixel {
+        // Casting a float to u8 saturates, and maps NaN to 0
+        let scale = |v: u8| (v as f32 * factor + 0.5) as u8;
+        px!(scale(self.r), scale(self.g), scale(self.b))
+    }
+}
+
+/// Unpacks a pixel from `0x00RRGGBB`, with red in the third least significant byte and blue in
+/// the least significant byte. The most significant byte is ignored.
+///
+/// # Example
+///
+/// ```
+/// use bmp::Pixel;
+///
+/// assert_eq!(Pixel::new(0x12, 0x34, 0x56), Pixel::from(0x0012_3456));
+/// assert_eq!(0x0012_3456, u32::from(Pixel::new(0x12, 0x34, 0x56)));
+/// ```
+impl From<u32> for Pixel {
+    fn from(rgb: u32) -> Pixel {
+        px!(rgb >> 16, rgb >> 8, rgb)
+    }
+}
+
+/// Packs a pixel as `0x00RRGGBB`, the most significant byte is always zero.
+impl From<Pixel> for u32 {
+    fn from(px: Pixel) -> u32 {
+        ((px.r as u32) << 16) | ((px.g as u32) << 8) | px.b as u32
+    }
+}
+
+/// An ordered list of colors, as stored by an indexed BMP file.
+///
+/// # Example
+///
+/// ```
+/// use bmp::{consts, Palette};
+///
+/// let palette = Palette::grayscale(2);
+/// assert_eq!(&[consts::BLACK, consts::WHITE][..], palette.colors());
+/// assert_eq!(1, palette.nearest(bmp::Pixel::new(200, 150, 180)));
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Palette {
+    colors: Vec<Pixel>,
+}
+
+impl Palette {
+    /// Creates a new `Palette` from its colors, in index order.
+    pub fn new(colors: Vec<Pixel>) -> Palette {
+        Palette { colors }
+    }
+
+    /// Returns `n` evenly spaced gray levels from black to white.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is zero or greater than 256.
+    pub fn grayscale(n: usize) -> Palette {
+        assert!(n > 0 && n <= 256, "A grayscale palette has between 1 and 256 levels");
+        let max = (n - 1).max(1);
+        Palette::new((0..n).map(|i| px!((i * 255 + max / 2) / max)).collect())
+    }
+
+    /// Returns the 216 colors of the web-safe palette, where every channel is one of 0, 51, 102,
+    /// 153, 204 or 255. Red varies the slowest and blue the fastest.
+    pub fn web_safe() -> Palette {
+        let colors = (0..216).map(|i| px!(i / 36 * 51, i / 6 % 6 * 51, i % 6 * 51));
+        Palette::new(colors.collect())
+    }
+
+    /// Returns the number of colors in the palette.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    /// Returns `true` if the palette has no colors.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    /// Returns the colors of the palette, in index order.
+    #[inline]
+    pub fn colors(&self) -> &[Pixel] {
+        &self.colors
+    }
+
+    /// Returns the index of the color closest to `px`, by squared Euclidean distance in RGB
+    /// space. Ties go to the lowest index, like `Image::quantize_to_palette`.
+    ///
+    /// # Panics
+    ///
+    /// If the palette is empty.
+    pub fn nearest(&self, px: Pixel) -> usize {
+        assert!(!self.is_empty(), "An empty palette has no nearest color");
+        (0..self.colors.len()).min_by_key(|&i| px.distance_sq(self.colors[i])).unwrap()
+    }
+}
+
+impl From<Vec<Pixel>> for Palette {
+    fn from(colors: Vec<Pixel>) -> Palette {
+        Palette::new(colors)
+    }
+}
+
+impl AsRef<[Pixel]> for Palette {
+    fn as_ref(&self) -> &[Pixel] {
+        &self.colors
+    }
+}
+
+/// A rectangular region of an image.
+///
+/// `(x, y)` is the upper left corner of the region, using the same coordinate convention as
+/// `Image`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Creates a new `Rect`.
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect { x, y, width, height }
+    }
+}
+
+/// The filters that can be used to resize an `Image`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum ResizeFilter {
+    /// Each pixel takes the color of the source pixel under its center.
+    Nearest,
+    /// Each pixel interpolates between the four source pixels around its center.
+    Bilinear,
+    /// Each pixel is the average of the block of source pixels it covers. Best for downscaling.
+    Area,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -51,6 +360,9 @@ enum BmpVersion {
     Two,
     Three,
     ThreeNT,
+    // The 52 and 56-byte Adobe extensions of the v3 header
+    ThreeRgbMasks,
+    ThreeRgbaMasks,
     Four,
     Five,
 }
@@ -61,6 +373,8 @@ impl BmpVersion {
             12 => Some(BmpVersion::Two),
             40 if dib_header.compress_type == 3 => Some(BmpVersion::ThreeNT),
             40 => Some(BmpVersion::Three),
+            52 => Some(BmpVersion::ThreeRgbMasks),
+            56 => Some(BmpVersion::ThreeRgbaMasks),
             108 => Some(BmpVersion::Four),
             124 => Some(BmpVersion::Five),
             _ => None,
@@ -74,6 +388,8 @@ impl AsRef<str> for BmpVersion {
             BmpVersion::Two => "BMP Version 2",
             BmpVersion::Three => "BMP Version 3",
             BmpVersion::ThreeNT => "BMP Version 3 NT",
+            BmpVersion::ThreeRgbMasks => "BMP Version 3 with RGB masks",
+            BmpVersion::ThreeRgbaMasks => "BMP Version 3 with RGBA masks",
             BmpVersion::Four => "BMP Version 4",
             BmpVersion::Five => "BMP Version 5",
         }
@@ -143,6 +459,11 @@ struct BmpDibHeader {
     vres: i32,
     num_colors: u32,
     num_imp_colors: u32,
+    // Only used with BITFIELDS compression
+    red_mask: u32,
+    green_mask: u32,
+    blue_mask: u32,
+    alpha_mask: u32,
 }
 
 impl BmpDibHeader {
@@ -160,26 +481,31 @@ impl BmpDibHeader {
             vres: 1000,
             num_colors: 0,
             num_imp_colors: 0,
+            red_mask: 0,
+            green_mask: 0,
+            blue_mask: 0,
+            alpha_mask: 0,
         }
     }
 }
 
 /// The image type provided by the library.
 ///
-/// It exposes functions to initialize or read BMP images from disk, common modification of pixel
-/// data, and saving to disk.
+/// It exposes functions to initialize or decode BMP images, common modification of pixel data,
+/// and encoding. With the `std` feature, images can also be read from and saved to disk.
 ///
 /// The image is accessed in row-major order from top to bottom,
 /// where point (0, 0) is defined to be in the upper left corner of the image.
 ///
-/// Currently, only uncompressed BMP images are supported.
+/// Uncompressed, BITFIELDS, RLE8 and RLE4 BMP images are supported.
 #[derive(Clone, Eq, PartialEq)]
 pub struct Image {
     header: BmpHeader,
     dib_header: BmpDibHeader,
-    color_palette: Option<Vec<Pixel>>,
+    color_palette: Option<Palette>,
     width: u32,
     height: u32,
+    // The number of bytes padding each row to a multiple of 4, at the bit depth of the image
     padding: u32,
     data: Vec<Pixel>,
 }
@@ -194,11 +520,35 @@ impl Image {
     /// let mut img = bmp::Image::new(100, 80);
     /// ```
     pub fn new(width: u32, height: u32) -> Image {
-        let mut data = Vec::with_capacity((width * height) as usize);
-        for _ in 0..width * height {
-            data.push(px!(0, 0, 0));
+        Image::new_filled(width, height, consts::BLACK)
+    }
+
+    /// Returns a new BMP Image with the `width` and `height` specified, with every pixel set to
+    /// `color`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new_filled(4, 3, bmp::consts::RED);
+    /// assert_eq!(bmp::consts::RED, img.get_pixel(3, 2));
+    /// ```
+    pub fn new_filled(width: u32, height: u32, color: Pixel) -> Image {
+        Image::with_data(width, height, alloc::vec![color; width as usize * height as usize])
+    }
+
+    // Returns a new image whose pixel at `(x, y)` is `f(x, y)`. The pixels are allocated once and
+    // written in storage order, rather than filled with black and then set one by one.
+    fn from_fn<F: FnMut(u32, u32) -> Pixel>(width: u32, height: u32, mut f: F) -> Image {
+        let mut data = Vec::with_capacity(width as usize * height as usize);
+        // Rows are stored bottom-up
+        for y in (0..height).rev() {
+            data.extend((0..width).map(|x| f(x, y)));
         }
+        Image::with_data(width, height, data)
+    }
 
+    // Returns a new 24-bit image of the given dimensions, storing `data` bottom-up
+    fn with_data(width: u32, height: u32, data: Vec<Pixel>) -> Image {
         let (header_size, data_size) = file_size!(24, width, height);
         Image {
             header: BmpHeader::new(header_size, data_size),
@@ -206,7 +556,7 @@ impl Image {
             color_palette: None,
             width: width,
             height: height,
-            padding: width % 4,
+            padding: row_padding(width, 24),
             data: data,
         }
     }
@@ -223,6 +573,32 @@ impl Image {
         self.height
     }
 
+    /// Returns the size in bytes of one row of pixels at the bit depth of the image, including
+    /// the padding to a multiple of 4 bytes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// // Three pixels of 24 bits are padded from 9 to 12 bytes
+    /// assert_eq!(12, bmp::Image::new(3, 2).row_stride());
+    /// ```
+    #[inline]
+    pub fn row_stride(&self) -> u32 {
+        (self.dib_header.bits_per_pixel as u32 * self.width + 31) / 32 * 4
+    }
+
+    /// Returns the size in bytes of the pixel array at the bit depth of the image.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// assert_eq!(24, bmp::Image::new(3, 2).data_len_bytes());
+    /// ```
+    #[inline]
+    pub fn data_len_bytes(&self) -> u32 {
+        self.row_stride() * self.height
+    }
+
     /// Set the pixel value at the position of `width` and `height`.
     ///
     /// # Example
@@ -249,6 +625,24 @@ impl Image {
         self.data[((self.height - y - 1) * self.width + x) as usize]
     }
 
+    /// Returns the pixel value at `(x, y)`, or `None` if the position is outside of the image.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(100, 80);
+    /// assert_eq!(Some(bmp::consts::BLACK), img.get_pixel_checked(99, 79));
+    /// assert_eq!(None, img.get_pixel_checked(100, 0));
+    /// ```
+    #[inline]
+    pub fn get_pixel_checked(&self, x: u32, y: u32) -> Option<Pixel> {
+        if x < self.width && y < self.height {
+            Some(self.get_pixel(x, y))
+        } else {
+            None
+        }
+    }
+
     /// Returns a new `ImageIndex` that iterates over the image dimensions in top-bottom order.
     ///
     /// # Example
@@ -264,51 +658,3521 @@ impl Image {
         ImageIndex::new(self.width as u32, self.height as u32)
     }
 
-}
+    /// Returns an iterator over every pixel in top-bottom order, along with the `Window` of the
+    /// `(2 * radius + 1)` x `(2 * radius + 1)` neighborhood centered on it. Neighbors past the
+    /// edges of the image are clamped to the nearest edge pixel.
+    ///
+    /// This is the building block of filters such as convolutions, the median and morphology.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(3, 3);
+    /// img.set_pixel(1, 1, bmp::consts::WHITE);
+    /// for (x, y, window) in img.iter_window(1) {
+    ///     // The white pixel is in the neighborhood of every pixel
+    ///     assert!(window.pixels().any(|px| px == bmp::consts::WHITE));
+    /// }
+    /// ```
+    pub fn iter_window<'a>(
+        &'a self,
+        radius: u32,
+    ) -> impl Iterator<Item = (u32, u32, Window<'a>)> {
+        self.coordinates().map(move |(x, y)| (x, y, Window { image: self, x, y, radius }))
+    }
 
-/// An `Iterator` returning the `x` and `y` coordinates of an image.
-///
-/// It supports iteration over an image in row-major order,
-/// starting from in the upper left corner of the image.
-#[derive(Clone, Copy)]
-pub struct ImageIndex {
-    width: u32,
-    height: u32,
-    x: u32,
-    y: u32,
-}
+    /// Replaces every pixel with the result of `f`, which is called with the coordinates and the
+    /// current value of the pixel.
+    ///
+    /// The pixels are visited in the order they are stored in, from the bottom row to the top
+    /// one, which is faster than indexing them in `coordinates` order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(4, 4);
+    /// img.map_pixels(|x, y, px| if x == y { bmp::consts::WHITE } else { px });
+    /// assert_eq!(bmp::consts::WHITE, img.get_pixel(2, 2));
+    /// assert_eq!(bmp::consts::BLACK, img.get_pixel(2, 1));
+    /// ```
+    pub fn map_pixels<F: FnMut(u32, u32, Pixel) -> Pixel>(&mut self, mut f: F) {
+        let width = self.width.max(1) as usize;
+        for (row, pixels) in self.data.chunks_mut(width).enumerate() {
+            let y = self.height - 1 - row as u32;
+            for (x, px) in pixels.iter_mut().enumerate() {
+                *px = f(x as u32, y, *px);
+            }
+        }
+    }
 
-impl ImageIndex {
-    fn new(width: u32, height: u32) -> ImageIndex {
-        ImageIndex {
-            width,
-            height,
-            x: 0,
-            y: 0,
+    /// Returns an iterator over the rows of the image, from the top row to the bottom one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(4, 3);
+    /// img.set_pixel(1, 0, bmp::consts::RED);
+    /// let top = img.rows().next().unwrap();
+    /// assert_eq!(4, top.len());
+    /// assert_eq!(bmp::consts::RED, top[1]);
+    /// ```
+    #[inline]
+    pub fn rows(&self) -> Rev<Chunks<Pixel>> {
+        self.data.chunks(self.width.max(1) as usize).rev()
+    }
+
+    /// Returns an iterator over the mutable rows of the image, from the top row to the bottom one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(4, 3);
+    /// for px in img.rows_mut().next().unwrap() {
+    ///     *px = bmp::consts::RED;
+    /// }
+    /// assert_eq!(bmp::consts::RED, img.get_pixel(3, 0));
+    /// assert_eq!(bmp::consts::BLACK, img.get_pixel(3, 1));
+    /// ```
+    #[inline]
+    pub fn rows_mut(&mut self) -> Rev<ChunksMut<Pixel>> {
+        self.data.chunks_mut(self.width.max(1) as usize).rev()
+    }
+
+    /// Returns the color palette of the image, if it was decoded from an indexed BMP.
+    #[inline]
+    pub fn palette(&self) -> Option<&[Pixel]> {
+        self.color_palette.as_ref().map(|palette| palette.colors())
+    }
+
+    /// Replaces the color palette of the image, given as a `Palette` or a `Vec<Pixel>`.
+    ///
+    /// Returns an error if the image is not indexed (more than 8 bits per pixel), or if the
+    /// palette is empty or has more entries than the bit depth of the image can index.
+    pub fn set_palette<P: Into<Palette>>(&mut self, palette: P) -> BmpResult<()> {
+        let palette = palette.into();
+        let bpp = self.dib_header.bits_per_pixel;
+        if bpp > 8 {
+            return Err(BmpError::new(
+                BmpErrorKind::InvalidPalette,
+                "Only images with 8 or less bits per pixel can have a palette",
+            ));
+        }
+        if palette.is_empty() || palette.len() > 1 << bpp {
+            return Err(BmpError::new(
+                BmpErrorKind::InvalidPalette,
+                "The palette size does not fit the bits per pixel of the image",
+            ));
         }
+
+        self.dib_header.num_colors = palette.len() as u32;
+        self.color_palette = Some(palette);
+        Ok(())
     }
-}
 
-impl Iterator for ImageIndex {
-    type Item = (u32, u32);
+    /// Draws a line of `color` from `(x0, y0)` to `(x1, y1)`, both endpoints included.
+    ///
+    /// Uses Bresenham's algorithm, so only integer arithmetic is involved. Pixels that fall
+    /// outside of the image are skipped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// img.draw_line(0, 0, 9, 9, bmp::consts::RED);
+    /// assert_eq!(bmp::consts::RED, img.get_pixel(5, 5));
+    /// ```
+    pub fn draw_line(&mut self, x0: u32, y0: u32, x1: u32, y1: u32, color: Pixel) {
+        let (mut x, mut y) = (x0 as i64, y0 as i64);
+        let (x1, y1) = (x1 as i64, y1 as i64);
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let sx = if x < x1 { 1 } else { -1 };
+        let sy = if y < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
 
-    fn next(&mut self) -> Option<(u32, u32)> {
-        if self.x < self.width && self.y < self.height {
-            let this = Some((self.x, self.y));
-            self.x += 1;
-            if self.x == self.width {
-                self.x = 0;
-                self.y += 1;
+        loop {
+            if x < self.width as i64 && y < self.height as i64 {
+                self.set_pixel(x as u32, y as u32, color);
+            }
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
             }
-            this
-        } else {
-            None
         }
     }
-}
 
-/// Attempts to construct a new `Image` from the given reader.
-/// Returns a `BmpResult`, either containing an `Image` or a `BmpError`.
-pub fn from_bytes(bytes: &[u8]) -> BmpResult<Image> {
-    decoder::decode_image(bytes)
-}
\ No newline at end of file
+    /// Draws the one pixel wide outline of the `w` by `h` rectangle whose upper left corner is
+    /// at `(x, y)`.
+    ///
+    /// The rectangle is clipped to the image bounds, and nothing is drawn if `w` or `h` is zero.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// img.draw_rect(2, 2, 5, 5, bmp::consts::RED);
+    /// assert_eq!(bmp::consts::RED, img.get_pixel(2, 4));
+    /// assert_eq!(bmp::consts::BLACK, img.get_pixel(4, 4));
+    /// ```
+    pub fn draw_rect(&mut self, x: u32, y: u32, w: u32, h: u32, color: Pixel) {
+        if w == 0 || h == 0 {
+            return;
+        }
+        let right = x.saturating_add(w - 1);
+        let bottom = y.saturating_add(h - 1);
+        self.fill_rect(x, y, w, 1, color);
+        self.fill_rect(x, bottom, w, 1, color);
+        self.fill_rect(x, y, 1, h, color);
+        self.fill_rect(right, y, 1, h, color);
+    }
+
+    /// Draws the outline of every `Rect` in `boxes` with its color, in order, such as the
+    /// results of a detector.
+    ///
+    /// Each box is clipped to the image bounds like in `draw_rect`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bmp::{consts, Rect};
+    ///
+    /// let mut img = bmp::Image::new(10, 10);
+    /// let boxes = [(Rect::new(0, 0, 3, 3), consts::RED), (Rect::new(5, 5, 9, 9), consts::BLUE)];
+    /// img.draw_boxes(&boxes);
+    /// assert_eq!(consts::RED, img.get_pixel(2, 1));
+    /// assert_eq!(consts::BLUE, img.get_pixel(9, 5));
+    /// ```
+    pub fn draw_boxes(&mut self, boxes: &[(Rect, Pixel)]) {
+        for &(rect, color) in boxes {
+            self.draw_rect(rect.x, rect.y, rect.width, rect.height, color);
+        }
+    }
+
+    /// Fills the `w` by `h` rectangle whose upper left corner is at `(x, y)` with `color`.
+    ///
+    /// The rectangle is clipped to the image bounds, and nothing is drawn if `w` or `h` is zero.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// img.fill_rect(2, 2, 5, 5, bmp::consts::RED);
+    /// assert_eq!(bmp::consts::RED, img.get_pixel(4, 4));
+    /// ```
+    pub fn fill_rect(&mut self, x: u32, y: u32, w: u32, h: u32, color: Pixel) {
+        let x_end = x.saturating_add(w).min(self.width);
+        let y_end = y.saturating_add(h).min(self.height);
+        for py in y..y_end {
+            for px in x..x_end {
+                self.set_pixel(px, py, color);
+            }
+        }
+    }
+
+    /// Reads and decodes the BMP file at `path`. Requires the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> BmpResult<Image> {
+        from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Saves the image to `path` as an uncompressed BMP file with 24 bits per pixel, replacing
+    /// any existing file. Requires the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    /// Encodes the image as an uncompressed BMP file with 24 bits per pixel.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(4, 4);
+    /// let bytes = img.to_bytes();
+    /// assert_eq!(img.get_pixel(1, 2), bmp::from_bytes(&bytes).unwrap().get_pixel(1, 2));
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encoder::encode_image(self)
+    }
+
+    /// Encodes the image as a 24-bit BMP file like `to_bytes`, followed by `thumb` as a second
+    /// 24-bit BMP file and a small trailer recording where it is stored. The thumbnail can be
+    /// read back with `extract_thumbnail`, and is ignored by other decoders.
+    ///
+    /// Returns an `ImageTooLarge` error if the main image or the thumbnail is 4 GiB or larger, as
+    /// the trailer records their sizes in 32 bits.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(64, 64);
+    /// let bytes = img.to_bytes_with_thumbnail(&img.resize_area(8, 8)).unwrap();
+    /// assert_eq!(64, bmp::from_bytes(&bytes).unwrap().get_width());
+    /// assert_eq!(8, bmp::extract_thumbnail(&bytes).unwrap().unwrap().get_width());
+    /// ```
+    pub fn to_bytes_with_thumbnail(&self, thumb: &Image) -> BmpResult<Vec<u8>> {
+        encoder::encode_image_with_thumbnail(self, thumb)
+    }
+
+    /// Encodes the image as a BMP file with 16 bits per pixel, using the given `format`.
+    ///
+    /// Colors are quantized to five (or six, for the green channel of `Rgb565`) bits per channel.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(4, 4);
+    /// let bytes = img.to_bytes_16(bmp::Bpp16Format::Rgb565);
+    /// assert_eq!(b"BM", &bytes[..2]);
+    /// ```
+    pub fn to_bytes_16(&self, format: Bpp16Format) -> Vec<u8> {
+        encoder::encode_image_16(self, format)
+    }
+
+    /// Encodes the image as an indexed BMP file with a palette of at most `max_colors` entries.
+    ///
+    /// If the image uses more than `max_colors` distinct colors, it is first reduced with a
+    /// median cut: the colors are repeatedly split at the median of their widest channel, and
+    /// each group is replaced by its average color. The smallest bit depth (1, 4 or 8 bits per
+    /// pixel) that can index the palette is used. The palette is sorted by the packed
+    /// `0x00RRGGBB` value of its colors, so the output only depends on the pixels of the image.
+    /// Returns an error if `max_colors` is zero or larger than 256.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(4, 4);
+    /// let bytes = img.to_bytes_indexed(256).unwrap();
+    /// let decoded = bmp::from_bytes(&bytes).unwrap();
+    /// assert_eq!(1, decoded.palette().unwrap().len());
+    /// ```
+    pub fn to_bytes_indexed(&self, max_colors: u16) -> BmpResult<Vec<u8>> {
+        encoder::encode_image_indexed(self, max_colors)
+    }
+
+    /// Encodes the image as an 8-bit indexed BMP file with RLE8 compression, which stores runs of
+    /// the same color in 2 bytes. The palette is sorted like the one of `to_bytes_indexed`.
+    ///
+    /// Unlike `to_bytes_indexed`, the colors are never quantized, so an error is returned if the
+    /// image has more than 256 of them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(100, 100);
+    /// let bytes = img.to_bytes_rle8().unwrap();
+    /// assert!(bytes.len() < img.to_bytes_indexed(256).unwrap().len());
+    /// assert!(img.eq_pixels(&bmp::from_bytes(&bytes).unwrap()));
+    /// ```
+    pub fn to_bytes_rle8(&self) -> BmpResult<Vec<u8>> {
+        encoder::encode_image_rle(self, 8)
+    }
+
+    /// Encodes the image as a 4-bit indexed BMP file with RLE4 compression, like `to_bytes_rle8`.
+    ///
+    /// Returns an error if the image has more than 16 colors.
+    pub fn to_bytes_rle4(&self) -> BmpResult<Vec<u8>> {
+        encoder::encode_image_rle(self, 4)
+    }
+
+    /// Returns the number of pixels of the color `c`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// img.fill_rect(0, 0, 3, 2, bmp::consts::RED);
+    /// assert_eq!(6, img.count_color(bmp::consts::RED));
+    /// ```
+    pub fn count_color(&self, c: Pixel) -> u32 {
+        self.data.iter().filter(|&&px| px == c).count() as u32
+    }
+
+    /// Returns the number of distinct colors in the image.
+    ///
+    /// Use `has_at_most_colors` to only check the number against a bound, which can stop early.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// img.set_pixel(0, 0, bmp::consts::RED);
+    /// assert_eq!(2, img.unique_colors());
+    /// ```
+    pub fn unique_colors(&self) -> usize {
+        self.data.iter().map(|&px| u32::from(px)).collect::<BTreeSet<_>>().len()
+    }
+
+    /// Returns the average color of the image, each channel rounded to the nearest integer.
+    ///
+    /// The sums are accumulated in `u64`, so they can't overflow. An image without pixels
+    /// averages to black.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 1);
+    /// img.set_pixel(0, 0, bmp::Pixel::new(255, 100, 1));
+    /// assert_eq!(bmp::Pixel::new(128, 50, 1), img.mean_color());
+    /// ```
+    pub fn mean_color(&self) -> Pixel {
+        let count = self.data.len() as u64;
+        if count == 0 {
+            return consts::BLACK;
+        }
+        let mut sums = [0u64; 3];
+        for px in &self.data {
+            sums[0] += px.r as u64;
+            sums[1] += px.g as u64;
+            sums[2] += px.b as u64;
+        }
+        let mean = |sum: u64| (sum + count / 2) / count;
+        px!(mean(sums[0]), mean(sums[1]), mean(sums[2]))
+    }
+
+    /// Returns the most frequent color of the image.
+    ///
+    /// Ties go to the color with the lowest packed `0x00RRGGBB` value, so the result is
+    /// deterministic. An image without pixels returns black.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(3, 1);
+    /// img.set_pixel(0, 0, bmp::consts::RED);
+    /// assert_eq!(bmp::consts::BLACK, img.dominant_color());
+    /// ```
+    pub fn dominant_color(&self) -> Pixel {
+        let mut counts = BTreeMap::new();
+        for &px in &self.data {
+            *counts.entry(u32::from(px)).or_insert(0u32) += 1;
+        }
+        // `max_by_key` returns the last maximum, so iterate from the highest value down
+        counts
+            .iter()
+            .rev()
+            .max_by_key(|&(_, &n)| n)
+            .map_or(consts::BLACK, |(&rgb, _)| Pixel::from(rgb))
+    }
+
+    /// Returns `true` if the image contains at most `n` distinct colors.
+    ///
+    /// The scan stops as soon as `n + 1` distinct colors have been seen, so this is cheap to
+    /// call on large photos even when `n` is small.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// img.set_pixel(0, 0, bmp::consts::RED);
+    /// assert!(img.has_at_most_colors(2));
+    /// assert!(!img.has_at_most_colors(1));
+    /// ```
+    pub fn has_at_most_colors(&self, n: usize) -> bool {
+        self.scan_colors(n).0
+    }
+
+    // Returns whether the image contains at most `n` distinct colors, and the number of pixels
+    // that were scanned to find out
+    fn scan_colors(&self, n: usize) -> (bool, usize) {
+        let mut seen = BTreeSet::new();
+        for (i, px) in self.data.iter().enumerate() {
+            seen.insert(u32::from(*px));
+            if seen.len() > n {
+                return (false, i + 1);
+            }
+        }
+        (true, self.data.len())
+    }
+
+    /// Returns the per-channel histogram of the image, indexed as `[r, g, b][value]`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(2, 2);
+    /// let hist = img.histogram();
+    /// assert_eq!(4, hist[0][0]);
+    /// ```
+    pub fn histogram(&self) -> [[u32; 256]; 3] {
+        let mut hist = [[0u32; 256]; 3];
+        for px in &self.data {
+            hist[0][px.r as usize] += 1;
+            hist[1][px.g as usize] += 1;
+            hist[2][px.b as usize] += 1;
+        }
+        hist
+    }
+
+    /// Splits the image into a grid of `cols` by `rows` cells and returns the histogram of every
+    /// cell, in row-major order from the upper left cell.
+    ///
+    /// Cell boundaries are distributed as evenly as possible; an empty `Vec` is returned if
+    /// `cols` or `rows` is zero. A grid larger than the image is reduced to one cell per pixel,
+    /// so `cols` is at most the width and `rows` at most the height.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(4, 4);
+    /// let cells = img.region_histograms(2, 2);
+    /// assert_eq!(4, cells.len());
+    /// assert_eq!(4, cells[3][0][0]);
+    /// ```
+    pub fn region_histograms(&self, cols: u32, rows: u32) -> Vec<[[u32; 256]; 3]> {
+        let cols = cols.min(self.width);
+        let rows = rows.min(self.height);
+        if cols == 0 || rows == 0 {
+            return Vec::new();
+        }
+        let mut cells = alloc::vec![[[0u32; 256]; 3]; cols as usize * rows as usize];
+        for (x, y) in self.coordinates() {
+            let cx = (x as u64 * cols as u64 / self.width as u64) as u32;
+            let cy = (y as u64 * rows as u64 / self.height as u64) as u32;
+            let px = self.get_pixel(x, y);
+            let hist = &mut cells[cy as usize * cols as usize + cx as usize];
+            hist[0][px.r as usize] += 1;
+            hist[1][px.g as usize] += 1;
+            hist[2][px.b as usize] += 1;
+        }
+        cells
+    }
+
+    /// Returns a copy of the image enhanced with contrast-limited adaptive histogram
+    /// equalization (CLAHE).
+    ///
+    /// The image is split into `tiles_x` by `tiles_y` tiles, and every channel of every tile is
+    /// equalized on its own. Histogram bins are clipped at `clip_limit` times the average bin
+    /// count before equalizing, which limits how much noise is amplified in flat tiles. The
+    /// mappings of the four nearest tiles are bilinearly interpolated to avoid block artifacts.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(16, 16);
+    /// let enhanced = img.clahe(4, 4, 2.0);
+    /// assert_eq!(16, enhanced.get_width());
+    /// ```
+    pub fn clahe(&self, tiles_x: u32, tiles_y: u32, clip_limit: f32) -> Image {
+        let tiles_x = tiles_x.min(self.width);
+        let tiles_y = tiles_y.min(self.height);
+        if tiles_x == 0 || tiles_y == 0 {
+            return self.clone();
+        }
+
+        // Build the equalization mapping of every channel in every tile
+        let mut luts = Vec::with_capacity(tiles_x as usize * tiles_y as usize);
+        for (i, hist) in self.region_histograms(tiles_x, tiles_y).iter().enumerate() {
+            let (tx, ty) = (i as u32 % tiles_x, i as u32 / tiles_x);
+            // Computed in u64, so wide images with many tiles can't overflow
+            let bound = |t: u32, size: u32, tiles: u32| t as u64 * size as u64 / tiles as u64;
+            let tile_w = bound(tx + 1, self.width, tiles_x) - bound(tx, self.width, tiles_x);
+            let tile_h = bound(ty + 1, self.height, tiles_y) - bound(ty, self.height, tiles_y);
+            let total = tile_w * tile_h;
+            let limit = ((clip_limit * total as f32 / 256.0) as u32).max(1);
+
+            let mut tile_luts = [[0u8; 256]; 3];
+            for (channel, lut) in hist.iter().zip(tile_luts.iter_mut()) {
+                let mut clipped = *channel;
+                let mut excess = 0;
+                for bin in clipped.iter_mut() {
+                    if *bin > limit {
+                        excess += *bin - limit;
+                        *bin = limit;
+                    }
+                }
+                // Redistribute the clipped counts evenly over all bins
+                for (v, bin) in clipped.iter_mut().enumerate() {
+                    *bin += excess / 256 + if (v as u32) < excess % 256 { 1 } else { 0 };
+                }
+                let mut cdf = 0;
+                for (v, bin) in clipped.iter().enumerate() {
+                    cdf += bin;
+                    lut[v] = ((cdf as u64 * 255 + total / 2) / total) as u8;
+                }
+            }
+            luts.push(tile_luts);
+        }
+
+        // Position of a pixel relative to the tile centers, in 1/256th of a tile
+        let tile_position = |pos: u32, size: u32, tiles: u32| {
+            let scaled = ((2 * pos as i64 + 1) * tiles as i64 * 256) / (2 * size as i64) - 128;
+            let scaled = scaled.clamp(0, (tiles as i64 - 1) * 256);
+            let i0 = (scaled / 256) as u32;
+            (i0, (i0 + 1).min(tiles - 1), (scaled % 256) as u32)
+        };
+
+        let mut enhanced = self.clone();
+        for (x, y) in self.coordinates() {
+            let (x0, x1, wx) = tile_position(x, self.width, tiles_x);
+            let (y0, y1, wy) = tile_position(y, self.height, tiles_y);
+            let px = self.get_pixel(x, y);
+            let mut out = [0u8; 3];
+            for (c, &v) in [px.r, px.g, px.b].iter().enumerate() {
+                let lut = |tx: u32, ty: u32| {
+                    luts[(ty * tiles_x + tx) as usize][c][v as usize] as u32
+                };
+                let top = lut(x0, y0) * (256 - wx) + lut(x1, y0) * wx;
+                let bottom = lut(x0, y1) * (256 - wx) + lut(x1, y1) * wx;
+                out[c] = ((top * (256 - wy) + bottom * wy + (1 << 15)) >> 16) as u8;
+            }
+            enhanced.set_pixel(x, y, px!(out[0], out[1], out[2]));
+        }
+        enhanced
+    }
+
+    /// Replaces every pixel with the nearest color of `palette`, by squared Euclidean distance
+    /// in RGB space. Ties go to the color with the lowest index, so the result is deterministic.
+    ///
+    /// The image is left unchanged if the palette is empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bmp::consts::{BLACK, WHITE};
+    ///
+    /// let mut img = bmp::Image::new(2, 1);
+    /// img.set_pixel(1, 0, bmp::Pixel::new(200, 150, 180));
+    /// img.quantize_to_palette(&[BLACK, WHITE]);
+    /// assert_eq!(BLACK, img.get_pixel(0, 0));
+    /// assert_eq!(WHITE, img.get_pixel(1, 0));
+    /// ```
+    pub fn quantize_to_palette(&mut self, palette: &[Pixel]) {
+        for px in self.data.iter_mut() {
+            if let Some(&nearest) = palette.iter().min_by_key(|color| px.distance_sq(**color)) {
+                *px = nearest;
+            }
+        }
+    }
+
+    /// Replaces every pixel with a color of `palette` using Floyd-Steinberg dithering, which
+    /// diffuses the quantization error of each pixel onto its unvisited neighbors instead of
+    /// producing bands like `quantize_to_palette`.
+    ///
+    /// The pixels are visited from left to right and from top to bottom, and the errors are
+    /// accumulated in integers, so the result is the same on every target. The nearest color is
+    /// chosen as in `quantize_to_palette`, and the image is left unchanged if the palette is
+    /// empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bmp::consts::{BLACK, WHITE};
+    ///
+    /// let mut img = bmp::Image::new(4, 4);
+    /// img.fill_rect(0, 0, 4, 4, bmp::Pixel::new(128, 128, 128));
+    /// img.dither_floyd_steinberg(&[BLACK, WHITE]);
+    /// let whites = img.coordinates().filter(|&(x, y)| img.get_pixel(x, y) == WHITE).count();
+    /// assert_eq!(8, whites);
+    /// ```
+    pub fn dither_floyd_steinberg(&mut self, palette: &[Pixel]) {
+        if palette.is_empty() {
+            return;
+        }
+
+        // The errors diffused onto the current and the next row, in 1/16 units. An extra column
+        // on each side takes the errors diffused past the edges.
+        let width = self.width as usize;
+        let mut current = alloc::vec![[0i32; 3]; width + 2];
+        let mut next = alloc::vec![[0i32; 3]; width + 2];
+        for y in 0..self.height {
+            for x in 0..width {
+                let px = self.get_pixel(x as u32, y);
+                let error = current[x + 1];
+                let wanted = |c: u8, e: i32| (c as i32 + (e + 8).div_euclid(16)).clamp(0, 255);
+                let r = wanted(px.r, error[0]);
+                let g = wanted(px.g, error[1]);
+                let b = wanted(px.b, error[2]);
+                let target = px!(r as u8, g as u8, b as u8);
+                let distance = |color: &&Pixel| target.distance_sq(**color);
+                let nearest = *palette.iter().min_by_key(distance).unwrap();
+                self.set_pixel(x as u32, y, nearest);
+
+                let diff = [r - nearest.r as i32, g - nearest.g as i32, b - nearest.b as i32];
+                for c in 0..3 {
+                    current[x + 2][c] += 7 * diff[c];
+                    next[x][c] += 3 * diff[c];
+                    next[x + 1][c] += 5 * diff[c];
+                    next[x + 2][c] += diff[c];
+                }
+            }
+            core::mem::swap(&mut current, &mut next);
+            for error in next.iter_mut() {
+                *error = [0; 3];
+            }
+        }
+    }
+
+    /// Returns `true` if both images have the same dimensions and pixels, ignoring their headers
+    /// and palettes.
+    ///
+    /// Unlike `==`, an image decoded from a file and the same pixels drawn on `Image::new` are
+    /// equal, even if one of them is indexed or their header fields differ.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(3, 2);
+    /// img.set_pixel(1, 1, bmp::consts::RED);
+    /// let bytes = img.to_bytes_indexed(256).unwrap();
+    /// let decoded = bmp::from_bytes(&bytes).unwrap();
+    /// assert!(img != decoded);
+    /// assert!(img.eq_pixels(&decoded));
+    /// ```
+    pub fn eq_pixels(&self, other: &Image) -> bool {
+        self.width == other.width && self.height == other.height && self.data == other.data
+    }
+
+    /// Like `eq_pixels`, but a sample of evenly spaced pixels is compared first, so images that
+    /// differ in many places are usually rejected without scanning all of their pixels.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(10, 10);
+    /// let mut other = img.clone();
+    /// assert!(img.quick_eq(&other));
+    /// other.set_pixel(9, 9, bmp::consts::WHITE);
+    /// assert!(!img.quick_eq(&other));
+    /// ```
+    pub fn quick_eq(&self, other: &Image) -> bool {
+        if self.width != other.width || self.height != other.height {
+            return false;
+        }
+        let step = (self.data.len() / 64).max(1);
+        let sample = |img: &Image| img.data.iter().step_by(step).fold(0u64, |hash, px| {
+            (hash ^ u32::from(*px) as u64).wrapping_mul(0x100_0000_01b3)
+        });
+        sample(self) == sample(other) && self.eq_pixels(other)
+    }
+
+    /// Returns the tightest `Rect` containing every pixel where any channel differs from `other`
+    /// by more than `threshold`, or `None` if no pixel does.
+    ///
+    /// Returns an error if the two images don't have the same dimensions.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(10, 10);
+    /// let mut edited = img.clone();
+    /// edited.set_pixel(3, 4, bmp::consts::RED);
+    /// let region = img.changed_region(&edited, 0).unwrap();
+    /// assert_eq!(Some(bmp::Rect::new(3, 4, 1, 1)), region);
+    /// ```
+    pub fn changed_region(&self, other: &Image, threshold: u8) -> BmpResult<Option<Rect>> {
+        if self.width != other.width || self.height != other.height {
+            return Err(BmpError::new(
+                BmpErrorKind::DimensionMismatch,
+                "Cannot compare images with different dimensions",
+            ));
+        }
+
+        let differs = |a: u8, b: u8| (a as i16 - b as i16).abs() > threshold as i16;
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+        for (x, y) in self.coordinates() {
+            let (p, q) = (self.get_pixel(x, y), other.get_pixel(x, y));
+            if differs(p.r, q.r) || differs(p.g, q.g) || differs(p.b, q.b) {
+                bounds = Some(match bounds {
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                    None => (x, y, x, y),
+                });
+            }
+        }
+
+        Ok(bounds.map(|(x0, y0, x1, y1)| Rect::new(x0, y0, x1 - x0 + 1, y1 - y0 + 1)))
+    }
+
+    /// Returns `true` if every channel of the image is within `tolerance` of `template` at
+    /// every pixel where `mask` is not black. Pixels where `mask` is black, such as the
+    /// editable fields of a form, are not compared.
+    ///
+    /// Returns an error if the template or the mask don't have the same dimensions as the image.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let template = bmp::Image::new(4, 4);
+    /// let mut mask = template.clone();
+    /// mask.fill_rect(0, 0, 4, 4, bmp::consts::WHITE);
+    /// mask.fill_rect(1, 1, 2, 2, bmp::consts::BLACK);
+    ///
+    /// let mut filled = template.clone();
+    /// filled.set_pixel(1, 2, bmp::consts::RED);
+    /// assert!(filled.matches_template(&template, &mask, 0).unwrap());
+    /// ```
+    pub fn matches_template(
+        &self,
+        template: &Image,
+        mask: &Image,
+        tolerance: u8,
+    ) -> BmpResult<bool> {
+        let same_size = |other: &Image| self.width == other.width && self.height == other.height;
+        if !same_size(template) || !same_size(mask) {
+            return Err(BmpError::new(
+                BmpErrorKind::DimensionMismatch,
+                "The template and the mask must have the same dimensions as the image",
+            ));
+        }
+
+        let within = |a: u8, b: u8| (a as i16 - b as i16).abs() <= tolerance as i16;
+        let matches = self
+            .data
+            .iter()
+            .zip(template.data.iter())
+            .zip(mask.data.iter())
+            .filter(|&(_, m)| *m != consts::BLACK)
+            .all(|((p, q), _)| within(p.r, q.r) && within(p.g, q.g) && within(p.b, q.b));
+        Ok(matches)
+    }
+
+    /// Returns the color of the region `rect` if it is flat, that is if every channel varies by
+    /// at most `tolerance` across the region, and `None` otherwise. The color returned is the one
+    /// of the upper left pixel of the region. An empty region has no color.
+    ///
+    /// Returns an error if the region doesn't lie within the image.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// img.fill_rect(2, 2, 4, 4, bmp::consts::BLACK);
+    /// let rect = bmp::Rect::new(2, 2, 4, 4);
+    /// assert_eq!(Some(bmp::consts::BLACK), img.region_is_uniform(rect, 0).unwrap());
+    /// ```
+    pub fn region_is_uniform(&self, rect: Rect, tolerance: u8) -> BmpResult<Option<Pixel>> {
+        if !self.contains_rect(rect) {
+            return Err(BmpError::new(
+                BmpErrorKind::OutOfBounds,
+                "The region must lie within the image",
+            ));
+        }
+        if rect.width == 0 || rect.height == 0 {
+            return Ok(None);
+        }
+
+        let first = self.get_pixel(rect.x, rect.y);
+        let (mut min, mut max) = (first, first);
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                let p = self.get_pixel(x, y);
+                min = px!(min.r.min(p.r), min.g.min(p.g), min.b.min(p.b));
+                max = px!(max.r.max(p.r), max.g.max(p.g), max.b.max(p.b));
+            }
+        }
+
+        let flat = max.r - min.r <= tolerance
+            && max.g - min.g <= tolerance
+            && max.b - min.b <= tolerance;
+        Ok(if flat { Some(first) } else { None })
+    }
+
+    /// Returns a copy of the image with its contrast stretched to the full range, ignoring the
+    /// darkest `low_pct` and the brightest `high_pct` percent of the pixels by luminance.
+    ///
+    /// Every channel is mapped linearly so that the luminance at the low percentile becomes 0 and
+    /// the one at the high percentile becomes 255, clamping the pixels outside of that range. A
+    /// few outliers therefore don't prevent the rest of the image from being stretched. The image
+    /// is returned unchanged if both percentiles have the same luminance.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 1);
+    /// img.fill_rect(0, 0, 5, 1, bmp::Pixel::new(100, 100, 100));
+    /// img.fill_rect(5, 0, 5, 1, bmp::Pixel::new(150, 150, 150));
+    /// let stretched = img.stretch_percentile(1.0, 1.0);
+    /// assert_eq!(bmp::consts::BLACK, stretched.get_pixel(0, 0));
+    /// assert_eq!(bmp::consts::WHITE, stretched.get_pixel(9, 0));
+    /// ```
+    pub fn stretch_percentile(&self, low_pct: f32, high_pct: f32) -> Image {
+        let mut hist = [0u64; 256];
+        for px in &self.data {
+            hist[px.luminance() as usize] += 1;
+        }
+        let total = self.data.len() as f32;
+        let clipped = |pct: f32| (total * pct.clamp(0.0, 100.0) / 100.0) as u64;
+
+        // The first level at which more than the clipped number of pixels has been seen, from
+        // either end of the histogram
+        let find = |clip: u64, from_top: bool| {
+            let mut seen = 0;
+            (0..256).map(|i| if from_top { 255 - i } else { i }).find(|&v| {
+                seen += hist[v];
+                seen > clip
+            })
+        };
+        let low = find(clipped(low_pct), false);
+        let high = find(clipped(high_pct), true);
+        let (low, high) = match (low, high) {
+            (Some(low), Some(high)) if low < high => (low as i32, high as i32),
+            _ => return self.clone(),
+        };
+
+        let mut stretched = self.clone();
+        let stretch = |v: u8| ((v as i32 - low) * 255 / (high - low)).clamp(0, 255) as u8;
+        for px in stretched.data.iter_mut() {
+            *px = px!(stretch(px.r), stretch(px.g), stretch(px.b));
+        }
+        stretched
+    }
+
+    /// Equalizes the luminance histogram of the image, spreading the levels in use over the full
+    /// range to increase its contrast.
+    ///
+    /// Each luminance is mapped through the cumulative histogram, so the darkest level becomes 0
+    /// and the brightest one 255. The same change is added to every channel of a pixel, which
+    /// keeps its chroma. Only integer arithmetic is used, so the result is the same in a guest.
+    /// An image with a single luminance is left unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 1);
+    /// img.set_pixel(0, 0, bmp::Pixel::new(100, 100, 100));
+    /// img.set_pixel(1, 0, bmp::Pixel::new(110, 110, 110));
+    /// img.equalize_histogram();
+    /// assert_eq!(bmp::consts::BLACK, img.get_pixel(0, 0));
+    /// assert_eq!(bmp::consts::WHITE, img.get_pixel(1, 0));
+    /// ```
+    pub fn equalize_histogram(&mut self) {
+        let mut hist = [0u64; 256];
+        for px in &self.data {
+            hist[px.luminance() as usize] += 1;
+        }
+        let mut cdf = [0u64; 256];
+        let mut seen = 0;
+        for (v, &count) in hist.iter().enumerate() {
+            seen += count;
+            cdf[v] = seen;
+        }
+
+        // The darkest level in use maps to 0
+        let first = hist.iter().position(|&count| count > 0).map_or(0, |v| cdf[v]);
+        let total = self.data.len() as u64;
+        if total == first {
+            return;
+        }
+        let mut delta = [0i32; 256];
+        for (v, d) in delta.iter_mut().enumerate() {
+            let above = cdf[v].saturating_sub(first);
+            let level = (above * 255 + (total - first) / 2) / (total - first);
+            *d = level as i32 - v as i32;
+        }
+
+        for px in self.data.iter_mut() {
+            let d = delta[px.luminance() as usize];
+            let shift = |c: u8| (c as i32 + d).clamp(0, 255) as u8;
+            *px = px!(shift(px.r), shift(px.g), shift(px.b));
+        }
+    }
+
+    /// Returns a copy of the image where every odd row, counting from the top one, is darkened
+    /// by subtracting `darkness` from each channel, like the scanlines of a CRT screen.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 2);
+    /// img.fill_rect(0, 0, 2, 2, bmp::consts::WHITE);
+    /// let crt = img.scanlines(55);
+    /// assert_eq!(bmp::consts::WHITE, crt.get_pixel(0, 0));
+    /// assert_eq!(bmp::Pixel::new(200, 200, 200), crt.get_pixel(0, 1));
+    /// ```
+    pub fn scanlines(&self, darkness: u8) -> Image {
+        let mut crt = self.clone();
+        for row in crt.rows_mut().skip(1).step_by(2) {
+            for px in row {
+                *px = *px - px!(darkness);
+            }
+        }
+        crt
+    }
+
+    /// Returns a copy of the image where every channel of every pixel is the median of that
+    /// channel over its 3x3 neighborhood, with the edges clamped.
+    ///
+    /// This removes salt-and-pepper noise without blurring edges, for example before a
+    /// `threshold`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(3, 3);
+    /// img.set_pixel(1, 1, bmp::consts::WHITE);
+    /// assert_eq!(bmp::consts::BLACK, img.median3x3().get_pixel(1, 1));
+    /// ```
+    pub fn median3x3(&self) -> Image {
+        let mut filtered = self.clone();
+        for (x, y, window) in self.iter_window(1) {
+            let mut channels = [[0u8; 9]; 3];
+            for (i, px) in window.pixels().enumerate() {
+                channels[0][i] = px.r;
+                channels[1][i] = px.g;
+                channels[2][i] = px.b;
+            }
+            for channel in channels.iter_mut() {
+                channel.sort_unstable();
+            }
+            filtered.set_pixel(x, y, px!(channels[0][4], channels[1][4], channels[2][4]));
+        }
+        filtered
+    }
+
+    /// Returns a binary black and white version of the image, where every pixel with a
+    /// luminance of at least `level` becomes white and every other pixel becomes black.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 1);
+    /// img.set_pixel(1, 0, bmp::consts::WHITE);
+    /// let mask = img.threshold(128);
+    /// assert_eq!(bmp::consts::BLACK, mask.get_pixel(0, 0));
+    /// assert_eq!(bmp::consts::WHITE, mask.get_pixel(1, 0));
+    /// ```
+    pub fn threshold(&self, level: u8) -> Image {
+        let mut binary = self.clone();
+        for px in binary.data.iter_mut() {
+            *px = if px.luminance() >= level { consts::WHITE } else { consts::BLACK };
+        }
+        binary
+    }
+
+    /// Remaps every channel through its 256-entry lookup table, so `r` becomes `r_lut[r]`.
+    ///
+    /// Gamma correction, inversion, brightness and contrast changes can all be expressed as
+    /// tables, see `gamma_lut`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut invert = [0; 256];
+    /// for (i, v) in invert.iter_mut().enumerate() {
+    ///     *v = 255 - i as u8;
+    /// }
+    /// let mut img = bmp::Image::new(1, 1);
+    /// img.set_pixel(0, 0, bmp::Pixel::new(0, 100, 255));
+    /// img.apply_lut(&invert, &invert, &invert);
+    /// assert_eq!(bmp::Pixel::new(255, 155, 0), img.get_pixel(0, 0));
+    /// ```
+    pub fn apply_lut(&mut self, r_lut: &[u8; 256], g_lut: &[u8; 256], b_lut: &[u8; 256]) {
+        for px in self.data.iter_mut() {
+            *px = px!(r_lut[px.r as usize], g_lut[px.g as usize], b_lut[px.b as usize]);
+        }
+    }
+
+    /// Returns a grayscale copy of the image where only the pixels within a Euclidean distance of
+    /// `tolerance` of `keep` retain their color, the "color splash" effect.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 1);
+    /// img.set_pixel(0, 0, bmp::consts::RED);
+    /// img.set_pixel(1, 0, bmp::consts::BLUE);
+    /// let splash = img.monochrome_accent(bmp::consts::RED, 0);
+    /// assert_eq!(bmp::consts::RED, splash.get_pixel(0, 0));
+    /// assert_eq!(bmp::Pixel::new(29, 29, 29), splash.get_pixel(1, 0));
+    /// ```
+    pub fn monochrome_accent(&self, keep: Pixel, tolerance: u8) -> Image {
+        let max_distance_sq = tolerance as u32 * tolerance as u32;
+        let mut splash = self.clone();
+        for px in splash.data.iter_mut() {
+            if px.distance_sq(keep) > max_distance_sq {
+                *px = px!(px.luminance());
+            }
+        }
+        splash
+    }
+
+    /// Fills the image with a linear gradient from `start` to `end`, in the direction given by
+    /// `angle_deg`.
+    ///
+    /// An angle of 0 goes from left to right, and 90 from top to bottom. The gradient spans the
+    /// whole image, so the two corners furthest apart along the direction get `start` and
+    /// `end`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bmp::consts::{BLUE, RED};
+    ///
+    /// let mut img = bmp::Image::new(8, 4);
+    /// img.fill_linear_gradient(RED, BLUE, 90.0);
+    /// assert_eq!(RED, img.get_pixel(5, 0));
+    /// assert_eq!(BLUE, img.get_pixel(5, 3));
+    /// ```
+    pub fn fill_linear_gradient(&mut self, start: Pixel, end: Pixel, angle_deg: f32) {
+        let (sin, cos) = sin_cos_deg(angle_deg);
+        let (cx, cy) = ((self.width as f32 - 1.0) / 2.0, (self.height as f32 - 1.0) / 2.0);
+        // Half of the length of the image along the direction of the gradient
+        let abs = |v: f32| if v < 0.0 { -v } else { v };
+        let half = abs(cos) * cx + abs(sin) * cy;
+
+        self.map_pixels(|x, y, _| {
+            let t = if half > 0.0 {
+                ((x as f32 - cx) * cos + (y as f32 - cy) * sin + half) / (2.0 * half)
+            } else {
+                0.5
+            };
+            start.lerp(end, t)
+        });
+    }
+
+    /// Returns a grayscale `width` by `height` mask with a radial falloff, going from `inner` at
+    /// the center of the image to `outer` at the corners.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mask = bmp::Image::radial_mask(5, 5, 255, 0);
+    /// assert_eq!(bmp::consts::WHITE, mask.get_pixel(2, 2));
+    /// assert_eq!(bmp::consts::BLACK, mask.get_pixel(0, 0));
+    /// ```
+    pub fn radial_mask(width: u32, height: u32, inner: u8, outer: u8) -> Image {
+        let mut mask = Image::new(width, height);
+        // Work with doubled coordinates so the center falls on an integer even for even sizes
+        let (cx, cy) = (width as i64 - 1, height as i64 - 1);
+        let max_dist = isqrt(((cx * cx + cy * cy) as u64) << 16) as i64;
+        for (x, y) in mask.coordinates() {
+            let (dx, dy) = (2 * x as i64 - cx, 2 * y as i64 - cy);
+            let dist = isqrt(((dx * dx + dy * dy) as u64) << 16) as i64;
+            let value = match max_dist {
+                0 => inner as i64,
+                _ => inner as i64 + (outer as i64 - inner as i64) * dist / max_dist,
+            };
+            mask.set_pixel(x, y, px!(value, value, value));
+        }
+        mask
+    }
+
+    /// Returns a `width` by `height` image of the single color `color`, the same as `new_filled`.
+    pub fn solid(width: u32, height: u32, color: Pixel) -> Image {
+        Image::new_filled(width, height, color)
+    }
+
+    /// Returns a checkerboard of square cells of `cell` pixels, alternating between `a` and
+    /// `b`. The upper left cell is `a`.
+    ///
+    /// # Panics
+    ///
+    /// If `cell` is zero.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bmp::consts::{BLACK, WHITE};
+    ///
+    /// let board = bmp::Image::checkerboard(8, 8, WHITE, BLACK, 2);
+    /// assert_eq!(WHITE, board.get_pixel(1, 1));
+    /// assert_eq!(BLACK, board.get_pixel(2, 1));
+    /// ```
+    pub fn checkerboard(width: u32, height: u32, a: Pixel, b: Pixel, cell: u32) -> Image {
+        assert!(cell > 0, "The cells must be at least one pixel wide");
+        let mut board = Image::new(width, height);
+        board.map_pixels(|x, y, _| if (x / cell + y / cell) % 2 == 0 { a } else { b });
+        board
+    }
+
+    /// Returns a gradient going from `left` in the first column to `right` in the last one,
+    /// interpolated per channel and rounded to the nearest integer.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bmp::consts::{BLACK, WHITE};
+    ///
+    /// let gradient = bmp::Image::gradient_horizontal(5, 2, BLACK, WHITE);
+    /// assert_eq!(BLACK, gradient.get_pixel(0, 1));
+    /// assert_eq!(bmp::Pixel::new(128, 128, 128), gradient.get_pixel(2, 0));
+    /// assert_eq!(WHITE, gradient.get_pixel(4, 1));
+    /// ```
+    pub fn gradient_horizontal(width: u32, height: u32, left: Pixel, right: Pixel) -> Image {
+        let mut gradient = Image::new(width, height);
+        let span = width.max(2) - 1;
+        let mix = |l: u8, r: u8, x: u32| (l as u32 * (span - x) + r as u32 * x + span / 2) / span;
+        let column: Vec<Pixel> = (0..width)
+            .map(|x| px!(mix(left.r, right.r, x), mix(left.g, right.g, x), mix(left.b, right.b, x)))
+            .collect();
+        for row in gradient.rows_mut() {
+            row.copy_from_slice(&column);
+        }
+        gradient
+    }
+
+    /// Returns a copy of the region `rect` of the image.
+    ///
+    /// Returns an error if `rect` doesn't lie entirely within the image.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// img.set_pixel(4, 3, bmp::consts::RED);
+    /// let cropped = img.crop(bmp::Rect::new(4, 3, 2, 2)).unwrap();
+    /// assert_eq!(bmp::consts::RED, cropped.get_pixel(0, 0));
+    /// ```
+    pub fn crop(&self, rect: Rect) -> BmpResult<Image> {
+        if !self.contains_rect(rect) {
+            return Err(BmpError::new(
+                BmpErrorKind::OutOfBounds,
+                "The region to crop must lie within the image",
+            ));
+        }
+
+        Ok(Image::from_fn(rect.width, rect.height, |x, y| {
+            self.get_pixel(rect.x + x, rect.y + y)
+        }))
+    }
+
+    /// Returns a view of the `width` x `height` region whose upper left corner is at `(x, y)`,
+    /// without copying its pixels like `crop` does.
+    ///
+    /// Returns an error if the region doesn't lie within the image.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// img.set_pixel(4, 3, bmp::consts::RED);
+    /// let view = img.sub_image(4, 3, 2, 2).unwrap();
+    /// assert_eq!(bmp::consts::RED, view.get_pixel(0, 0));
+    /// ```
+    pub fn sub_image(&self, x: u32, y: u32, width: u32, height: u32) -> BmpResult<SubImage> {
+        let rect = Rect::new(x, y, width, height);
+        if !self.contains_rect(rect) {
+            return Err(BmpError::new(
+                BmpErrorKind::OutOfBounds,
+                "The region of the view must lie within the image",
+            ));
+        }
+        Ok(SubImage { image: self, rect })
+    }
+
+    /// Returns the image cropped to the tightest region containing every pixel that differs from
+    /// `bg`, removing a uniform frame such as the margins of a scanned document. If every pixel
+    /// is `bg`, a 1x1 image of `bg` is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// img.fill_rect(0, 0, 10, 10, bmp::consts::WHITE);
+    /// img.fill_rect(3, 4, 2, 3, bmp::consts::RED);
+    /// let trimmed = img.trim_border(bmp::consts::WHITE);
+    /// assert_eq!((2, 3), (trimmed.get_width(), trimmed.get_height()));
+    /// ```
+    pub fn trim_border(&self, bg: Pixel) -> Image {
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+        for (x, y) in self.coordinates().filter(|&(x, y)| self.get_pixel(x, y) != bg) {
+            bounds = Some(match bounds {
+                Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                None => (x, y, x, y),
+            });
+        }
+
+        match bounds {
+            Some((x0, y0, x1, y1)) => self
+                .crop(Rect::new(x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+                .expect("The bounds lie within the image"),
+            None => {
+                let mut background = Image::new(1, 1);
+                background.set_pixel(0, 0, bg);
+                background
+            }
+        }
+    }
+
+    /// Returns a copy of the image rotated by 90 degrees clockwise.
+    ///
+    /// Indexed images keep their palette and bit depth, so they can be re-encoded compactly.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(3, 2);
+    /// img.set_pixel(0, 0, bmp::consts::RED);
+    /// let rotated = img.rotate90();
+    /// assert_eq!((2, 3), (rotated.get_width(), rotated.get_height()));
+    /// assert_eq!(bmp::consts::RED, rotated.get_pixel(1, 0));
+    /// ```
+    pub fn rotate90(&self) -> Image {
+        self.swapped_dimensions(|x, y| self.get_pixel(y, self.height - 1 - x))
+    }
+
+    /// Returns a copy of the image with its rows and columns swapped, so the pixel at `(x, y)`
+    /// moves to `(y, x)`.
+    ///
+    /// Indexed images keep their palette and bit depth, like with `rotate90`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(3, 2);
+    /// img.set_pixel(2, 0, bmp::consts::RED);
+    /// let transposed = img.transpose();
+    /// assert_eq!((2, 3), (transposed.get_width(), transposed.get_height()));
+    /// assert_eq!(bmp::consts::RED, transposed.get_pixel(0, 2));
+    /// ```
+    pub fn transpose(&self) -> Image {
+        self.swapped_dimensions(|x, y| self.get_pixel(y, x))
+    }
+
+    // Returns an image with the headers and palette of this one, whose width and height are
+    // swapped, and whose pixel at `(x, y)` is `f(x, y)`
+    fn swapped_dimensions<F: FnMut(u32, u32) -> Pixel>(&self, f: F) -> Image {
+        let mut swapped = Image::from_fn(self.height, self.width, f);
+        swapped.header = self.header.clone();
+        swapped.dib_header = self.dib_header.clone();
+        swapped.color_palette = self.color_palette.clone();
+        swapped.padding = row_padding(swapped.width, self.dib_header.bits_per_pixel);
+        swapped.dib_header.width = swapped.width as i32;
+        swapped.dib_header.height = swapped.height as i32;
+        let bpp = self.dib_header.bits_per_pixel;
+        let (_, data_size) = file_size!(bpp, swapped.width, swapped.height);
+        swapped.dib_header.data_size = data_size;
+        swapped.header.file_size = swapped.header.pixel_offset + data_size;
+        swapped
+    }
+
+    /// Returns an error if the image is not square.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// assert!(bmp::Image::new(4, 4).assert_square().is_ok());
+    /// assert!(bmp::Image::new(4, 3).assert_square().is_err());
+    /// ```
+    pub fn assert_square(&self) -> BmpResult<()> {
+        if self.width != self.height {
+            return Err(BmpError::new(
+                BmpErrorKind::DimensionMismatch,
+                "The image must have the same width and height",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns a square copy of the image, centered on a canvas of `background` whose side is
+    /// the larger of the width and the height.
+    ///
+    /// If the padding can't be split evenly, the extra row or column goes to the bottom or the
+    /// right.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(6, 2);
+    /// let square = img.pad_to_square_centered(bmp::consts::WHITE);
+    /// assert!(square.assert_square().is_ok());
+    /// assert_eq!(bmp::consts::WHITE, square.get_pixel(0, 0));
+    /// assert_eq!(bmp::consts::BLACK, square.get_pixel(0, 2));
+    /// ```
+    pub fn pad_to_square_centered(&self, background: Pixel) -> Image {
+        let side = self.width.max(self.height);
+        let mut square = Image::new_filled(side, side, background);
+        square.blit(self, (side - self.width) / 2, (side - self.height) / 2);
+        square
+    }
+
+    /// Returns a copy of the image surrounded by a border of `color`, with the given number of
+    /// pixels on each side. This is the inverse of `trim_border`.
+    ///
+    /// # Panics
+    ///
+    /// If the padded width or height doesn't fit in a `u32`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(2, 2);
+    /// let padded = img.pad(1, 0, 3, 0, bmp::consts::WHITE);
+    /// assert_eq!((5, 3), (padded.get_width(), padded.get_height()));
+    /// assert_eq!(bmp::consts::WHITE, padded.get_pixel(2, 1));
+    /// assert_eq!(bmp::consts::BLACK, padded.get_pixel(3, 1));
+    /// ```
+    pub fn pad(&self, top: u32, bottom: u32, left: u32, right: u32, color: Pixel) -> Image {
+        let grow = |size: u32, before: u32, after: u32| {
+            size.checked_add(before)
+                .and_then(|size| size.checked_add(after))
+                .expect("The padded dimensions must fit in a u32")
+        };
+        let (width, height) = (grow(self.width, left, right), grow(self.height, top, bottom));
+        Image::from_fn(width, height, |x, y| {
+            let inside = (left..left + self.width).contains(&x)
+                && (top..top + self.height).contains(&y);
+            if inside {
+                self.get_pixel(x - left, y - top)
+            } else {
+                color
+            }
+        })
+    }
+
+    /// Copies the pixels of `src` onto this image, with the upper left corner of `src` placed
+    /// at `(dest_x, dest_y)`.
+    ///
+    /// Pixels that would fall outside of this image are clipped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// let mut logo = bmp::Image::new(2, 2);
+    /// logo.set_pixel(0, 0, bmp::consts::RED);
+    /// img.blit(&logo, 4, 4);
+    /// assert_eq!(bmp::consts::RED, img.get_pixel(4, 4));
+    /// ```
+    pub fn blit(&mut self, src: &Image, dest_x: u32, dest_y: u32) {
+        for (x, y) in src.coordinates() {
+            match (dest_x.checked_add(x), dest_y.checked_add(y)) {
+                (Some(dx), Some(dy)) if dx < self.width && dy < self.height => {
+                    self.set_pixel(dx, dy, src.get_pixel(x, y));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Blends the pixels of `top` onto this image with an opacity of `alpha`, with the upper left
+    /// corner of `top` placed at `(x, y)`.
+    ///
+    /// Pixels that would fall outside of this image are clipped, like in `blit`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(10, 10);
+    /// let mut watermark = bmp::Image::new(4, 2);
+    /// watermark.fill_rect(0, 0, 4, 2, bmp::consts::WHITE);
+    /// img.overlay(&watermark, 6, 8, 64);
+    /// assert_eq!(bmp::Pixel::new(64, 64, 64), img.get_pixel(9, 9));
+    /// ```
+    pub fn overlay(&mut self, top: &Image, x: u32, y: u32, alpha: u8) {
+        for (tx, ty) in top.coordinates() {
+            match (x.checked_add(tx), y.checked_add(ty)) {
+                (Some(dx), Some(dy)) if dx < self.width && dy < self.height => {
+                    let below = self.get_pixel(dx, dy);
+                    self.set_pixel(dx, dy, below.blend(top.get_pixel(tx, ty), alpha));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Returns the red, green and blue channels of the image as separate planes.
+    ///
+    /// Each plane holds `width * height` values in top-down row-major order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 2);
+    /// img.set_pixel(1, 0, bmp::consts::RED);
+    /// let (r, g, b) = img.to_planar();
+    /// assert_eq!(vec![0, 255, 0, 0], r);
+    /// assert_eq!(vec![0; 4], g);
+    /// assert_eq!(vec![0; 4], b);
+    /// ```
+    pub fn to_planar(&self) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let size = self.data.len();
+        let mut r = Vec::with_capacity(size);
+        let mut g = Vec::with_capacity(size);
+        let mut b = Vec::with_capacity(size);
+        for (x, y) in self.coordinates() {
+            let px = self.get_pixel(x, y);
+            r.push(px.r);
+            g.push(px.g);
+            b.push(px.b);
+        }
+        (r, g, b)
+    }
+
+    /// Creates an image from separate red, green and blue planes, as returned by `to_planar`.
+    ///
+    /// Returns an error if any of the planes doesn't hold exactly `width * height` values.
+    pub fn from_planar(width: u32, height: u32, r: &[u8], g: &[u8], b: &[u8]) -> BmpResult<Image> {
+        let size = (width as usize).checked_mul(height as usize);
+        if size.is_none() || [r.len(), g.len(), b.len()].iter().any(|&len| Some(len) != size) {
+            return Err(BmpError::new(
+                BmpErrorKind::DimensionMismatch,
+                "Every plane must hold exactly width * height values",
+            ));
+        }
+
+        let mut img = Image::new(width, height);
+        for (i, (x, y)) in img.coordinates().enumerate() {
+            img.set_pixel(x, y, px!(r[i], g[i], b[i]));
+        }
+        Ok(img)
+    }
+
+    /// Returns the pixels as tightly packed R, G, B triples, in top-down row-major order.
+    ///
+    /// Unlike the pixel array of a BMP file, the rows are not padded and the channels are not
+    /// stored in BGR order, so the buffer can be exchanged with other image libraries.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 1);
+    /// img.set_pixel(1, 0, bmp::Pixel::new(1, 2, 3));
+    /// assert_eq!(vec![0, 0, 0, 1, 2, 3], img.as_rgb_bytes());
+    /// ```
+    pub fn as_rgb_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(3 * self.data.len());
+        for (x, y) in self.coordinates() {
+            let px = self.get_pixel(x, y);
+            bytes.extend_from_slice(&[px.r, px.g, px.b]);
+        }
+        bytes
+    }
+
+    /// Encodes the image as a binary PPM (P6) file, which many viewers and tools can read and
+    /// which has no padding or palette to get wrong, so it is handy for debugging.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(3, 2);
+    /// let ppm = img.to_ppm();
+    /// assert_eq!(b"P6\n3 2\n255\n", &ppm[..11]);
+    /// ```
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut ppm = alloc::format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        ppm.extend_from_slice(&self.as_rgb_bytes());
+        ppm
+    }
+
+    /// Creates an image from tightly packed R, G, B triples, as returned by `as_rgb_bytes`.
+    ///
+    /// Returns an error if `bytes` doesn't hold exactly `3 * width * height` values.
+    pub fn from_rgb_bytes(width: u32, height: u32, bytes: &[u8]) -> BmpResult<Image> {
+        let size = (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(3));
+        if size != Some(bytes.len()) {
+            return Err(BmpError::new(
+                BmpErrorKind::DimensionMismatch,
+                "The buffer must hold exactly 3 * width * height values",
+            ));
+        }
+
+        let mut img = Image::new(width, height);
+        for ((x, y), rgb) in img.coordinates().zip(bytes.chunks(3)) {
+            img.set_pixel(x, y, px!(rgb[0], rgb[1], rgb[2]));
+        }
+        Ok(img)
+    }
+
+    /// Returns the red, green and blue channels of the image as separate planes, like
+    /// `to_planar`.
+    pub fn split_channels(&self) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        self.to_planar()
+    }
+
+    /// Creates an image from the planes returned by `split_channels`, like `from_planar`.
+    ///
+    /// Returns an error if any of the planes doesn't hold exactly `width * height` values.
+    pub fn merge_channels(
+        width: u32,
+        height: u32,
+        r: &[u8],
+        g: &[u8],
+        b: &[u8],
+    ) -> BmpResult<Image> {
+        Image::from_planar(width, height, r, g, b)
+    }
+
+    /// Returns a copy of the image reduced to `target_width` columns by seam carving.
+    ///
+    /// Instead of scaling uniformly, vertical seams of low energy (according to a Sobel filter
+    /// on the luma channel) are removed one at a time, so prominent content is preserved while
+    /// flat areas shrink. The image is returned unchanged if it is not wider than `target_width`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let img = bmp::Image::new(10, 4);
+    /// assert_eq!(6, img.carve_width(6).get_width());
+    /// ```
+    pub fn carve_width(&self, target_width: u32) -> Image {
+        let height = self.height as usize;
+        let mut width = self.width as usize;
+        // Work on a top-down buffer where every row keeps its original stride
+        let stride = width;
+        let mut rows: Vec<Pixel> = Vec::with_capacity(stride * height);
+        for (x, y) in self.coordinates() {
+            rows.push(self.get_pixel(x, y));
+        }
+
+        let mut seam = Vec::with_capacity(height);
+        while width > target_width as usize && height > 0 {
+            let energy = sobel_energy(&rows, stride, width, height);
+
+            // Accumulate the minimal seam cost from the top row down
+            let mut cost = energy;
+            for y in 1..height {
+                for x in 0..width {
+                    let lo = if x > 0 { x - 1 } else { x };
+                    let hi = if x + 1 < width { x + 1 } else { x };
+                    let best = (lo..hi + 1).map(|px| cost[(y - 1) * stride + px]).min().unwrap();
+                    cost[y * stride + x] += best;
+                }
+            }
+
+            // Backtrack from the cheapest bottom pixel, preferring the leftmost on ties
+            seam.clear();
+            let last = (height - 1) * stride;
+            let mut x = (0..width).min_by_key(|&px| cost[last + px]).unwrap();
+            seam.push(x);
+            for y in (0..height - 1).rev() {
+                let lo = if x > 0 { x - 1 } else { x };
+                let hi = if x + 1 < width { x + 1 } else { x };
+                x = (lo..hi + 1).min_by_key(|&px| cost[y * stride + px]).unwrap();
+                seam.push(x);
+            }
+
+            for (i, &x) in seam.iter().enumerate() {
+                let row = &mut rows[(height - 1 - i) * stride..(height - i) * stride];
+                for px in x..width - 1 {
+                    row[px] = row[px + 1];
+                }
+            }
+            width -= 1;
+        }
+
+        let mut carved = Image::new(width as u32, self.height);
+        for (x, y) in carved.coordinates() {
+            carved.set_pixel(x, y, rows[y as usize * stride + x as usize]);
+        }
+        carved
+    }
+
+    /// Returns the color at the position `(fx, fy)`, interpolated between the four pixels around
+    /// it. The center of the pixel at `(x, y)` is at `(x as f32, y as f32)`, and positions outside
+    /// of the image are clamped to its edges.
+    ///
+    /// Use `sample_bilinear_q8` where the result must not depend on floating point rounding,
+    /// such as in a guest.
+    ///
+    /// # Panics
+    ///
+    /// If the image has no pixels.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 1);
+    /// img.set_pixel(1, 0, bmp::consts::WHITE);
+    /// assert_eq!(bmp::Pixel::new(64, 64, 64), img.sample_bilinear(0.25, 0.0));
+    /// ```
+    pub fn sample_bilinear(&self, fx: f32, fy: f32) -> Pixel {
+        // Returns the two pixels around `f`, and the weight of the second one
+        let neighbors = |f: f32, size: u32| {
+            let f = f.clamp(0.0, (size - 1) as f32);
+            let first = f as u32;
+            (first, (first + 1).min(size - 1), f - first as f32)
+        };
+        let (x0, x1, wx) = neighbors(fx, self.width);
+        let (y0, y1, wy) = neighbors(fy, self.height);
+        let (a, b) = (self.get_pixel(x0, y0), self.get_pixel(x1, y0));
+        let (c, d) = (self.get_pixel(x0, y1), self.get_pixel(x1, y1));
+        let lerp = |p: u8, q: u8, w: f32| p as f32 + (q as f32 - p as f32) * w;
+        let channel = |p: u8, q: u8, r: u8, s: u8| {
+            (lerp(p, q, wx) + (lerp(r, s, wx) - lerp(p, q, wx)) * wy + 0.5) as u8
+        };
+        let r = channel(a.r, b.r, c.r, d.r);
+        let g = channel(a.g, b.g, c.g, d.g);
+        px!(r, g, channel(a.b, b.b, c.b, d.b))
+    }
+
+    /// Returns the color at the position `(fx, fy)` given in 1/256 pixel units, interpolated
+    /// between the four pixels around it with integer arithmetic only. The center of the pixel at
+    /// `(x, y)` is at `(x * 256, y * 256)`, and positions past the edges are clamped.
+    ///
+    /// # Panics
+    ///
+    /// If the image has no pixels.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 1);
+    /// img.set_pixel(1, 0, bmp::consts::WHITE);
+    /// assert_eq!(bmp::Pixel::new(64, 64, 64), img.sample_bilinear_q8(64, 0));
+    /// ```
+    pub fn sample_bilinear_q8(&self, fx: u32, fy: u32) -> Pixel {
+        // Returns the two pixels around `f`, and the weight of the second one in 1/256 units
+        let neighbors = |f: u32, size: u32| {
+            let first = (f >> 8).min(size - 1);
+            (first, (first + 1).min(size - 1), f & 255)
+        };
+        let (x0, x1, wx) = neighbors(fx, self.width);
+        let (y0, y1, wy) = neighbors(fy, self.height);
+        let (a, b) = (self.get_pixel(x0, y0), self.get_pixel(x1, y0));
+        let (c, d) = (self.get_pixel(x0, y1), self.get_pixel(x1, y1));
+        let lerp = |p: u8, q: u8, w: u32| p as u32 * (256 - w) + q as u32 * w;
+        let channel = |p: u8, q: u8, r: u8, s: u8| {
+            let top = lerp(p, q, wx);
+            let bottom = lerp(r, s, wx);
+            ((top * (256 - wy) + bottom * wy + (1 << 15)) >> 16) as u8
+        };
+        let r = channel(a.r, b.r, c.r, d.r);
+        let g = channel(a.g, b.g, c.g, d.g);
+        px!(r, g, channel(a.b, b.b, c.b, d.b))
+    }
+
+    /// Returns a copy of the image resized to `width` x `height` with the given `filter`.
+    ///
+    /// Pixel centers are aligned between the two images, and the result is black if the image
+    /// has no pixels.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bmp::{Image, ResizeFilter};
+    ///
+    /// let img = Image::new(100, 80);
+    /// let thumbnail = img.resize(25, 20, ResizeFilter::Area);
+    /// assert_eq!((25, 20), (thumbnail.get_width(), thumbnail.get_height()));
+    /// ```
+    pub fn resize(&self, width: u32, height: u32, filter: ResizeFilter) -> Image {
+        if self.width == 0 || self.height == 0 {
+            return Image::new(width, height);
+        }
+
+        match filter {
+            ResizeFilter::Nearest => {
+                let center = |o: u32, out: u32, src: u32| {
+                    ((2 * o as u64 + 1) * src as u64 / (2 * out as u64)) as u32
+                };
+                Image::from_fn(width, height, |x, y| {
+                    let (sx, sy) = (center(x, width, self.width), center(y, height, self.height));
+                    self.get_pixel(sx, sy)
+                })
+            }
+            ResizeFilter::Bilinear => {
+                // Returns the position of the center of `o` in the source, in 1/256 pixel units
+                let position = |o: u32, out: u32, src: u32| {
+                    let pos = ((2 * o as u64 + 1) * src as u64 * 128 / out as u64).max(128) - 128;
+                    pos.min(u32::MAX as u64) as u32
+                };
+                Image::from_fn(width, height, |x, y| {
+                    let sx = position(x, width, self.width);
+                    self.sample_bilinear_q8(sx, position(y, height, self.height))
+                })
+            }
+            ResizeFilter::Area => Image::from_fn(width, height, |x, y| {
+                let (x0, x1) = span(x, width, self.width);
+                let (y0, y1) = span(y, height, self.height);
+                let mut sum = [0u64; 3];
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        let px = self.get_pixel(sx, sy);
+                        sum[0] += px.r as u64;
+                        sum[1] += px.g as u64;
+                        sum[2] += px.b as u64;
+                    }
+                }
+                let count = (x1 - x0) as u64 * (y1 - y0) as u64;
+                let channel = |c: usize| ((sum[c] + count / 2) / count) as u8;
+                px!(channel(0), channel(1), channel(2))
+            }),
+        }
+    }
+
+    /// Returns a copy of the image resized with `ResizeFilter::Nearest`.
+    pub fn resize_nearest(&self, width: u32, height: u32) -> Image {
+        self.resize(width, height, ResizeFilter::Nearest)
+    }
+
+    /// Returns a copy of the image resized with `ResizeFilter::Bilinear`, where every pixel is
+    /// sampled with `sample_bilinear_q8` at its center in the source.
+    pub fn resize_bilinear(&self, width: u32, height: u32) -> Image {
+        self.resize(width, height, ResizeFilter::Bilinear)
+    }
+
+    /// Returns a copy of the image resized with `ResizeFilter::Area`.
+    pub fn resize_area(&self, width: u32, height: u32) -> Image {
+        self.resize(width, height, ResizeFilter::Area)
+    }
+
+    /// Returns a copy of the image scaled down to `width` x `height`, where every pixel is the
+    /// rounded integer average of the block of source pixels it covers.
+    ///
+    /// This is `resize_area` under the name of the box filter. Non-integer ratios are supported,
+    /// the blocks then have different sizes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 2);
+    /// img.set_pixel(0, 0, bmp::consts::WHITE);
+    /// assert_eq!(bmp::Pixel::new(64, 64, 64), img.downscale_box(1, 1).get_pixel(0, 0));
+    /// ```
+    pub fn downscale_box(&self, width: u32, height: u32) -> Image {
+        self.resize_area(width, height)
+    }
+
+    /// Returns a copy of the image scaled down to `width` x `height`, averaging every block of
+    /// source pixels in linear light.
+    ///
+    /// Averaging the sRGB encoded values directly darkens the result, which is especially
+    /// visible on fine patterns of saturated colors. Here each channel is converted to linear
+    /// light, averaged, and converted back to sRGB.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut img = bmp::Image::new(2, 1);
+    /// img.set_pixel(0, 0, bmp::consts::WHITE);
+    /// let small = img.downscale_average_linear(1, 1);
+    /// // Half of the light of white, rather than the sRGB midpoint 128
+    /// assert_eq!(bmp::Pixel::new(188, 188, 188), small.get_pixel(0, 0));
+    /// ```
+    pub fn downscale_average_linear(&self, width: u32, height: u32) -> Image {
+        if self.width == 0 || self.height == 0 {
+            return Image::new(width, height);
+        }
+
+        Image::from_fn(width, height, |x, y| {
+            let (x0, x1) = span(x, width, self.width);
+            let (y0, y1) = span(y, height, self.height);
+            let mut sum = [0u64; 3];
+            for sy in y0..y1 {
+                for sx in x0..x1 {
+                    let px = self.get_pixel(sx, sy);
+                    sum[0] += SRGB_TO_LINEAR[px.r as usize] as u64;
+                    sum[1] += SRGB_TO_LINEAR[px.g as usize] as u64;
+                    sum[2] += SRGB_TO_LINEAR[px.b as usize] as u64;
+                }
+            }
+            let count = (x1 - x0) as u64 * (y1 - y0) as u64;
+            let channel = |c: usize| linear_to_srgb(((sum[c] + count / 2) / count) as u16);
+            px!(channel(0), channel(1), channel(2))
+        })
+    }
+
+    // Returns `true` if `rect` lies within the image
+    fn contains_rect(&self, rect: Rect) -> bool {
+        let fits = |start: u32, len: u32, max: u32| {
+            start.checked_add(len).map_or(false, |end| end <= max)
+        };
+        fits(rect.x, rect.width, self.width) && fits(rect.y, rect.height, self.height)
+    }
+}
+
+impl fmt::Debug for Image {
+    // Summarize the image rather than dumping every pixel
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = BmpVersion::from_dib_header(&self.dib_header);
+        f.debug_struct("Image")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("version", &version.as_ref().map(|v| v.as_ref()).unwrap_or("Unknown"))
+            .field("data_len", &self.data.len())
+            .finish()
+    }
+}
+
+/// Decodes an image like `from_bytes`.
+///
+/// # Example
+///
+/// ```
+/// use std::convert::TryFrom;
+///
+/// let bytes = bmp::Image::new(2, 2).to_bytes();
+/// let img = bmp::Image::try_from(&bytes[..]).unwrap();
+/// assert_eq!(2, img.get_width());
+/// ```
+impl<'a> TryFrom<&'a [u8]> for Image {
+    type Error = BmpError;
+
+    fn try_from(bytes: &'a [u8]) -> BmpResult<Image> {
+        decoder::decode_image(bytes)
+    }
+}
+
+// The sRGB transfer function, mapping each 8-bit sRGB value to linear light in 0..=65535
+const SRGB_TO_LINEAR: [u16; 256] = [
+    0, 20, 40, 60, 80, 99, 119, 139, 159, 179, 199, 219,
+    241, 264, 288, 313, 340, 367, 396, 427, 458, 491, 526, 562,
+    599, 637, 677, 718, 761, 805, 851, 898, 947, 997, 1048, 1101,
+    1156, 1212, 1270, 1330, 1391, 1453, 1517, 1583, 1651, 1720, 1790, 1863,
+    1937, 2013, 2090, 2170, 2250, 2333, 2418, 2504, 2592, 2681, 2773, 2866,
+    2961, 3058, 3157, 3258, 3360, 3464, 3570, 3678, 3788, 3900, 4014, 4129,
+    4247, 4366, 4488, 4611, 4736, 4864, 4993, 5124, 5257, 5392, 5530, 5669,
+    5810, 5953, 6099, 6246, 6395, 6547, 6700, 6856, 7014, 7174, 7335, 7500,
+    7666, 7834, 8004, 8177, 8352, 8528, 8708, 8889, 9072, 9258, 9445, 9635,
+    9828, 10022, 10219, 10417, 10619, 10822, 11028, 11235, 11446, 11658, 11873, 12090,
+    12309, 12530, 12754, 12980, 13209, 13440, 13673, 13909, 14146, 14387, 14629, 14874,
+    15122, 15371, 15623, 15878, 16135, 16394, 16656, 16920, 17187, 17456, 17727, 18001,
+    18277, 18556, 18837, 19121, 19407, 19696, 19987, 20281, 20577, 20876, 21177, 21481,
+    21787, 22096, 22407, 22721, 23038, 23357, 23678, 24002, 24329, 24658, 24990, 25325,
+    25662, 26001, 26344, 26688, 27036, 27386, 27739, 28094, 28452, 28813, 29176, 29542,
+    29911, 30282, 30656, 31033, 31412, 31794, 32179, 32567, 32957, 33350, 33745, 34143,
+    34544, 34948, 35355, 35764, 36176, 36591, 37008, 37429, 37852, 38278, 38706, 39138,
+    39572, 40009, 40449, 40891, 41337, 41785, 42236, 42690, 43147, 43606, 44069, 44534,
+    45002, 45473, 45947, 46423, 46903, 47385, 47871, 48359, 48850, 49344, 49841, 50341,
+    50844, 51349, 51858, 52369, 52884, 53401, 53921, 54445, 54971, 55500, 56032, 56567,
+    57105, 57646, 58190, 58737, 59287, 59840, 60396, 60955, 61517, 62082, 62650, 63221,
+    63795, 64372, 64952, 65535,
+];
+
+// Returns the sRGB value whose linear light is closest to `linear`
+fn linear_to_srgb(linear: u16) -> u8 {
+    let i = match SRGB_TO_LINEAR.binary_search(&linear) {
+        Ok(i) => return i as u8,
+        Err(i) => i,
+    };
+    if i == 0 {
+        0
+    } else if i == SRGB_TO_LINEAR.len() ||
+        linear - SRGB_TO_LINEAR[i - 1] <= SRGB_TO_LINEAR[i] - linear
+    {
+        (i - 1) as u8
+    } else {
+        i as u8
+    }
+}
+
+// Approximates the sine and cosine of an angle in degrees with Bhaskara's formula, which is
+// accurate to about 0.002 and doesn't need the float functions of std
+fn sin_cos_deg(deg: f32) -> (f32, f32) {
+    let sin = |deg: f32| {
+        let mut x = deg % 360.0;
+        if x < 0.0 {
+            x += 360.0;
+        }
+        let (x, sign) = if x > 180.0 { (x - 180.0, -1.0) } else { (x, 1.0) };
+        sign * 4.0 * x * (180.0 - x) / (40500.0 - x * (180.0 - x))
+    };
+    (sin(deg), sin(deg + 90.0))
+}
+
+// Base 2 logarithm of a positive, normal `x`, accurate to about 1e-6 without the float
+// functions of std
+fn log2(x: f32) -> f32 {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127;
+    // The mantissa in [1, 2), whose natural logarithm converges quickly with the atanh series
+    let m = f32::from_bits((bits & 0x007F_FFFF) | 0x3F80_0000);
+    let t = (m - 1.0) / (m + 1.0);
+    let t2 = t * t;
+    let ln = 2.0 * t * (1.0 + t2 * (1.0 / 3.0 + t2 * (1.0 / 5.0 + t2 * (1.0 / 7.0 + t2 / 9.0))));
+    exponent as f32 + ln * core::f32::consts::LOG2_E
+}
+
+// 2 raised to the power `y`, flushed to zero below the smallest normal f32
+fn exp2(y: f32) -> f32 {
+    if y < -126.0 {
+        return 0.0;
+    }
+    let n = y as i32 - if y < 0.0 && y != (y as i32) as f32 { 1 } else { 0 };
+    let f = (y - n as f32) * core::f32::consts::LN_2;
+    // Taylor series of e^f for f in [0, ln 2)
+    let (mut term, mut sum) = (1.0, 1.0);
+    for k in 1..10 {
+        term *= f / k as f32;
+        sum += term;
+    }
+    sum * f32::from_bits(((n + 127) as u32) << 23)
+}
+
+// Returns the number of bytes padding a row of `width` pixels of `bpp` bits to a multiple of 4
+fn row_padding(width: u32, bpp: u16) -> u32 {
+    let row_bytes = (width as u64 * bpp as u64 + 7) / 8;
+    ((4 - row_bytes % 4) % 4) as u32
+}
+
+// Returns the range of source pixels covered by output pixel `o`, when scaling `src` pixels to
+// `out` pixels. Every output pixel covers at least one source pixel.
+fn span(o: u32, out: u32, src: u32) -> (u32, u32) {
+    let start = (o as u64 * src as u64 / out as u64) as u32;
+    let end = ((o as u64 + 1) * src as u64 / out as u64) as u32;
+    (start, end.max(start + 1))
+}
+
+// Integer square root, rounded down
+fn isqrt(n: u64) -> u64 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = n;
+    let mut y = (x + 1) / 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+// Computes the Sobel gradient magnitude (|gx| + |gy|) of the first `width` columns of a
+// top-down buffer, clamping at the borders
+fn sobel_energy(rows: &[Pixel], stride: usize, width: usize, height: usize) -> Vec<u32> {
+    let mut energy = Vec::with_capacity(stride * height);
+    for y in 0..height {
+        for x in 0..stride {
+            if x >= width {
+                energy.push(0);
+                continue;
+            }
+            let at = |dx: isize, dy: isize| {
+                let px = (x as isize + dx).clamp(0, width as isize - 1) as usize;
+                let py = (y as isize + dy).clamp(0, height as isize - 1) as usize;
+                rows[py * stride + px].luminance() as i32
+            };
+            let gx = at(1, -1) + 2 * at(1, 0) + at(1, 1) - at(-1, -1) - 2 * at(-1, 0) - at(-1, 1);
+            let gy = at(-1, 1) + 2 * at(0, 1) + at(1, 1) - at(-1, -1) - 2 * at(0, -1) - at(1, -1);
+            energy.push((gx.abs() + gy.abs()) as u32);
+        }
+    }
+    energy
+}
+
+/// An `Iterator` returning the `x` and `y` coordinates of an image.
+///
+/// It supports iteration over an image in row-major order,
+/// starting from in the upper left corner of the image.
+#[derive(Clone, Copy)]
+pub struct ImageIndex {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+}
+
+impl ImageIndex {
+    fn new(width: u32, height: u32) -> ImageIndex {
+        ImageIndex {
+            width,
+            height,
+            x: 0,
+            y: 0,
+        }
+    }
+}
+
+impl Iterator for ImageIndex {
+    type Item = (u32, u32);
+
+    fn next(&mut self) -> Option<(u32, u32)> {
+        if self.x < self.width && self.y < self.height {
+            let this = Some((self.x, self.y));
+            self.x += 1;
+            if self.x == self.width {
+                self.x = 0;
+                self.y += 1;
+            }
+            this
+        } else {
+            None
+        }
+    }
+}
+
+/// A borrowed view of a rectangular region of an `Image`, created by `Image::sub_image`.
+///
+/// The coordinates are relative to the upper left corner of the region, and the pixels are read
+/// from the parent image.
+#[derive(Clone, Copy)]
+pub struct SubImage<'a> {
+    image: &'a Image,
+    rect: Rect,
+}
+
+impl<'a> SubImage<'a> {
+    /// Returns the width of the view.
+    #[inline]
+    pub fn get_width(&self) -> u32 {
+        self.rect.width
+    }
+
+    /// Returns the height of the view.
+    #[inline]
+    pub fn get_height(&self) -> u32 {
+        self.rect.height
+    }
+
+    /// Returns the region of the parent image covered by the view.
+    #[inline]
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    /// Returns the pixel value at the position `(x, y)` of the view.
+    #[inline]
+    pub fn get_pixel(&self, x: u32, y: u32) -> Pixel {
+        assert!(x < self.rect.width && y < self.rect.height, "The pixel must lie within the view");
+        self.image.get_pixel(self.rect.x + x, self.rect.y + y)
+    }
+
+    /// Returns row `y` of the view as a slice of the parent image, from left to right.
+    pub fn row(&self, y: u32) -> &'a [Pixel] {
+        assert!(y < self.rect.height, "The row must lie within the view");
+        let start = ((self.image.height - self.rect.y - y - 1) * self.image.width
+            + self.rect.x) as usize;
+        &self.image.data[start..start + self.rect.width as usize]
+    }
+
+    /// Returns a new `ImageIndex` that iterates over the dimensions of the view.
+    #[inline]
+    pub fn coordinates(&self) -> ImageIndex {
+        ImageIndex::new(self.rect.width, self.rect.height)
+    }
+}
+
+/// The neighborhood of a pixel, yielded by `Image::iter_window`.
+///
+/// Offsets are relative to the center pixel, from `-radius` to `radius` in each direction, and
+/// neighbors past the edges of the image read the nearest edge pixel.
+#[derive(Clone, Copy)]
+pub struct Window<'a> {
+    image: &'a Image,
+    x: u32,
+    y: u32,
+    radius: u32,
+}
+
+impl<'a> Window<'a> {
+    /// Returns the radius of the window, which is `2 * radius + 1` pixels wide.
+    #[inline]
+    pub fn radius(&self) -> u32 {
+        self.radius
+    }
+
+    /// Returns the pixel the window is centered on.
+    #[inline]
+    pub fn center(&self) -> Pixel {
+        self.image.get_pixel(self.x, self.y)
+    }
+
+    /// Returns the pixel at the offset `(dx, dy)` from the center, clamped to the image.
+    ///
+    /// # Panics
+    ///
+    /// If an offset is larger than the radius.
+    pub fn get(&self, dx: i32, dy: i32) -> Pixel {
+        let r = self.radius as i64;
+        assert!(
+            (-r..=r).contains(&(dx as i64)) && (-r..=r).contains(&(dy as i64)),
+            "The offset must lie within the window"
+        );
+        let clamp = |c: u32, d: i32, max: u32| (c as i64 + d as i64).clamp(0, max as i64 - 1);
+        let x = clamp(self.x, dx, self.image.width);
+        self.image.get_pixel(x as u32, clamp(self.y, dy, self.image.height) as u32)
+    }
+
+    /// Returns an iterator over the pixels of the window, row by row from the top left.
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel> + 'a {
+        let (window, r) = (*self, self.radius as i32);
+        (-r..=r).flat_map(move |dy| (-r..=r).map(move |dx| window.get(dx, dy)))
+    }
+}
+
+/// Returns the lookup table raising every channel, scaled to `[0, 1]`, to the power `gamma`,
+/// for use with `Image::apply_lut`. A `gamma` below 1 brightens the image, above 1 darkens it.
+///
+/// # Panics
+///
+/// If `gamma` is not positive.
+///
+/// # Example
+///
+/// ```
+/// let lut = bmp::gamma_lut(2.0);
+/// assert_eq!((0, 64, 255), (lut[0], lut[128], lut[255]));
+/// assert_eq!(bmp::gamma_lut(1.0)[77], 77);
+/// ```
+pub fn gamma_lut(gamma: f32) -> [u8; 256] {
+    assert!(gamma > 0.0, "The gamma must be positive");
+    let mut lut = [0; 256];
+    for (i, v) in lut.iter_mut().enumerate().skip(1) {
+        *v = (255.0 * exp2(gamma * log2(i as f32 / 255.0)) + 0.5) as u8;
+    }
+    lut
+}
+
+/// Attempts to construct a new `Image` from the given reader.
+/// Returns a `BmpResult`, either containing an `Image` or a `BmpError`.
+///
+/// Images of more than `DEFAULT_MAX_PIXELS` pixels are rejected with `ImageTooLarge`. Malformed
+/// or truncated input is always reported as an error, and never panics, so untrusted bytes can
+/// be decoded safely.
+pub fn from_bytes(bytes: &[u8]) -> BmpResult<Image> {
+    decoder::decode_image(bytes)
+}
+
+/// Like `from_bytes`, but rejects images of more than `max_pixels` pixels with `ImageTooLarge`
+/// before allocating any of them, so a small malicious header can't exhaust the memory.
+///
+/// # Example
+///
+/// ```
+/// let bytes = bmp::Image::new(100, 100).to_bytes();
+/// assert!(bmp::from_bytes_with_limit(&bytes, 10_000).is_ok());
+/// assert!(bmp::from_bytes_with_limit(&bytes, 9_999).is_err());
+/// ```
+pub fn from_bytes_with_limit(bytes: &[u8], max_pixels: usize) -> BmpResult<Image> {
+    decoder::decode_image_with_limit(bytes, max_pixels)
+}
+
+/// Decodes the thumbnail embedded in a file written by `Image::to_bytes_with_thumbnail`.
+///
+/// Returns `None` if the file has no thumbnail trailer, and an error if the trailer points
+/// outside of the file or the thumbnail can't be decoded.
+pub fn extract_thumbnail(bytes: &[u8]) -> BmpResult<Option<Image>> {
+    decoder::decode_thumbnail(bytes)
+}
+
+/// Decodes the pixels of a BMP file into `buf`, replacing its contents, and returns the width
+/// and height of the image.
+///
+/// Unlike `from_bytes`, the buffer can be reused between images, and it is only grown once per
+/// image. The rows are stored from the bottom of the image to the top, as in the file.
+///
+/// # Example
+///
+/// ```
+/// let bytes = bmp::Image::new(4, 2).to_bytes();
+/// let mut buf = Vec::new();
+/// assert_eq!((4, 2), bmp::decode_into(&bytes, &mut buf).unwrap());
+/// assert_eq!(8, buf.len());
+/// ```
+pub fn decode_into(bytes: &[u8], buf: &mut Vec<Pixel>) -> BmpResult<(u32, u32)> {
+    decoder::decode_into(bytes, buf)
+}
+
+/// Decodes an image from a stream of bytes, pulling only as many as needed.
+///
+/// The headers and the palette are buffered up to the start of the pixels, which are then
+/// decoded one row at a time, so the file never has to be in memory as a whole. Run-length
+/// encoded pixels are buffered until the end of the stream, since their length is only known
+/// once they are decoded. Images of more than `DEFAULT_MAX_PIXELS` pixels are rejected like in
+/// `from_bytes`.
+///
+/// # Example
+///
+/// ```
+/// let bytes = bmp::Image::new(4, 2).to_bytes();
+/// let img = bmp::decode_from_iter(bytes.iter().cloned()).unwrap();
+/// assert_eq!(bmp::from_bytes(&bytes).unwrap(), img);
+/// ```
+pub fn decode_from_iter<I: Iterator<Item = u8>>(iter: I) -> BmpResult<Image> {
+    decoder::decode_from_iter(iter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use consts::{BLACK, RED};
+
+    /// Builds an uncompressed indexed BMP file with a BITMAPINFOHEADER.
+    ///
+    /// `indexes` are given in top-down row-major order.
+    pub fn indexed_bmp(
+        width: u32,
+        height: u32,
+        bpp: u16,
+        palette: &[Pixel],
+        indexes: &[u8],
+    ) -> Vec<u8> {
+        let row_size = ((bpp as u32 * width + 31) / 32 * 4) as usize;
+        let pixel_offset = 54 + 4 * palette.len() as u32;
+        let file_size = pixel_offset + row_size as u32 * height;
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"BM");
+        bytes.extend_from_slice(&file_size.to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&pixel_offset.to_le_bytes());
+        bytes.extend_from_slice(&40u32.to_le_bytes());
+        bytes.extend_from_slice(&(width as i32).to_le_bytes());
+        bytes.extend_from_slice(&(height as i32).to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&bpp.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&(row_size as u32 * height).to_le_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        for px in palette {
+            bytes.extend_from_slice(&[px.b, px.g, px.r, 0]);
+        }
+        for y in (0..height as usize).rev() {
+            let mut row = alloc::vec![0u8; row_size];
+            for x in 0..width as usize {
+                let bit = x * bpp as usize;
+                let shift = 8 - bpp as usize - bit % 8;
+                row[bit / 8] |= indexes[y * width as usize + x] << shift;
+            }
+            bytes.extend_from_slice(&row);
+        }
+        bytes
+    }
+
+    #[test]
+    fn pixel_u32_round_trip() {
+        let colors = [BLACK, consts::WHITE, RED, consts::LIME, consts::BLUE, px!(1, 2, 3)];
+        let packed = [0, 0xFF_FFFF, 0xFF_0000, 0x00_FF00, 0x00_00FF, 0x01_0203];
+        for (&px, &rgb) in colors.iter().zip(packed.iter()) {
+            assert_eq!(rgb, u32::from(px));
+            assert_eq!(px, Pixel::from(rgb));
+        }
+        // The high byte is ignored when unpacking
+        assert_eq!(consts::WHITE, Pixel::from(0xABFF_FFFF));
+    }
+
+    #[test]
+    fn pixel_arithmetic_saturates() {
+        let white = consts::WHITE;
+        assert_eq!(white, white + white);
+        for &px in [BLACK, RED, white, px!(1, 2, 3)].iter() {
+            assert_eq!(BLACK, BLACK - px);
+            assert_eq!(BLACK, px - px);
+            assert_eq!(px, px + BLACK);
+        }
+        assert_eq!(px!(200, 0, 50), px!(250, 10, 60) - px!(50, 20, 10));
+        assert_eq!(px!(255, 0, 60), px!(100, 0, 20) * 3u8);
+        assert_eq!(px!(10, 20, 128), px!(20, 40, 255) * 0.5);
+        assert_eq!(px!(255, 0, 255), px!(200, 0, 128) * 2.0);
+        assert_eq!(BLACK, RED * -1.0);
+    }
+
+    #[test]
+    fn pixel_lerp_clamps_t() {
+        assert_eq!(BLACK, BLACK.lerp(consts::WHITE, -1.0));
+        assert_eq!(px!(64, 64, 64), BLACK.lerp(consts::WHITE, 0.25));
+        assert_eq!(consts::WHITE, BLACK.lerp(consts::WHITE, 2.0));
+        assert_eq!(px!(191, 0, 64), RED.lerp(consts::BLUE, 0.25));
+    }
+
+    #[test]
+    fn sin_cos_deg_approximates_angles() {
+        let close = |a: f32, b: f32| a - b < 0.002 && b - a < 0.002;
+        for &(deg, sin, cos) in [
+            (0.0, 0.0, 1.0),
+            (30.0, 0.5, 0.866_025),
+            (45.0, 0.707_107, 0.707_107),
+            (-90.0, -1.0, 0.0),
+            (200.0, -0.342_020, -0.939_693),
+            (720.0, 0.0, 1.0),
+        ].iter() {
+            let (s, c) = sin_cos_deg(deg);
+            assert!(close(sin, s) && close(cos, c), "{}: {} {}", deg, s, c);
+        }
+    }
+
+    #[test]
+    fn fill_linear_gradient_at_45_degrees() {
+        let mut img = Image::new(9, 9);
+        img.fill_linear_gradient(RED, consts::BLUE, 45.0);
+        assert_eq!(RED, img.get_pixel(0, 0));
+        assert_eq!(consts::BLUE, img.get_pixel(8, 8));
+        // The other diagonal is halfway
+        assert_eq!(px!(128, 0, 128), img.get_pixel(8, 0));
+        assert_eq!(img.get_pixel(8, 0), img.get_pixel(0, 8));
+        assert_eq!(img.get_pixel(3, 1), img.get_pixel(1, 3));
+
+        // The opposite direction swaps the colors
+        img.fill_linear_gradient(RED, consts::BLUE, 225.0);
+        assert_eq!(consts::BLUE, img.get_pixel(0, 0));
+        assert_eq!(RED, img.get_pixel(8, 8));
+    }
+
+    #[test]
+    fn pixel_blend_at_the_extremes_and_midpoint() {
+        let (below, over) = (px!(10, 200, 0), px!(250, 0, 255));
+        assert_eq!(below, below.blend(over, 0));
+        assert_eq!(over, below.blend(over, 255));
+        assert_eq!(px!(130, 100, 128), below.blend(over, 128));
+    }
+
+    #[test]
+    fn overlay_blends_and_clips() {
+        let mut img = Image::new(4, 4);
+        img.fill_rect(0, 0, 4, 4, RED);
+        let mut top = Image::new(3, 3);
+        top.fill_rect(0, 0, 3, 3, consts::BLUE);
+
+        let mut unchanged = img.clone();
+        unchanged.overlay(&top, 0, 0, 0);
+        assert_eq!(img, unchanged);
+
+        let mut replaced = img.clone();
+        replaced.overlay(&top, 2, 2, 255);
+        assert_eq!(consts::BLUE, replaced.get_pixel(3, 3));
+        assert_eq!(RED, replaced.get_pixel(1, 3));
+
+        img.overlay(&top, 2, 1, 128);
+        assert_eq!(px!(127, 0, 128), img.get_pixel(2, 1));
+        assert_eq!(px!(127, 0, 128), img.get_pixel(3, 3));
+        assert_eq!(RED, img.get_pixel(1, 1));
+        assert_eq!(RED, img.get_pixel(3, 0));
+    }
+
+    #[test]
+    fn draw_horizontal_line() {
+        let mut img = Image::new(8, 4);
+        img.draw_line(1, 2, 6, 2, RED);
+        assert_eq!(RED, img.get_pixel(1, 2));
+        assert_eq!(RED, img.get_pixel(4, 2));
+        assert_eq!(RED, img.get_pixel(6, 2));
+        assert_eq!(BLACK, img.get_pixel(0, 2));
+        assert_eq!(BLACK, img.get_pixel(7, 2));
+        assert_eq!(BLACK, img.get_pixel(4, 1));
+    }
+
+    #[test]
+    fn draw_vertical_line() {
+        let mut img = Image::new(4, 8);
+        img.draw_line(2, 6, 2, 0, RED);
+        assert_eq!(RED, img.get_pixel(2, 0));
+        assert_eq!(RED, img.get_pixel(2, 3));
+        assert_eq!(RED, img.get_pixel(2, 6));
+        assert_eq!(BLACK, img.get_pixel(2, 7));
+        assert_eq!(BLACK, img.get_pixel(1, 3));
+    }
+
+    #[test]
+    fn draw_diagonal_line() {
+        let mut img = Image::new(5, 5);
+        img.draw_line(0, 0, 4, 4, RED);
+        for i in 0..5 {
+            assert_eq!(RED, img.get_pixel(i, i));
+        }
+        assert_eq!(BLACK, img.get_pixel(1, 0));
+        assert_eq!(BLACK, img.get_pixel(0, 1));
+    }
+
+    #[test]
+    fn draw_line_skips_pixels_outside_image() {
+        let mut img = Image::new(4, 4);
+        img.draw_line(2, 2, 10, 2, RED);
+        assert_eq!(RED, img.get_pixel(2, 2));
+        assert_eq!(RED, img.get_pixel(3, 2));
+    }
+
+    #[test]
+    fn draw_rect_only_colors_the_border() {
+        let mut img = Image::new(6, 6);
+        img.draw_rect(1, 1, 4, 4, RED);
+        for i in 1..5 {
+            assert_eq!(RED, img.get_pixel(i, 1));
+            assert_eq!(RED, img.get_pixel(i, 4));
+            assert_eq!(RED, img.get_pixel(1, i));
+            assert_eq!(RED, img.get_pixel(4, i));
+        }
+        assert_eq!(BLACK, img.get_pixel(2, 2));
+        assert_eq!(BLACK, img.get_pixel(3, 3));
+        assert_eq!(BLACK, img.get_pixel(0, 0));
+        assert_eq!(BLACK, img.get_pixel(5, 5));
+    }
+
+    #[test]
+    fn rotate90_keeps_the_palette_of_indexed_images() {
+        let palette = [BLACK, RED, consts::BLUE];
+        let bytes = indexed_bmp(3, 2, 8, &palette, &[0, 1, 2, 2, 1, 0]);
+        let img = from_bytes(&bytes).unwrap();
+        let rotated = img.rotate90();
+
+        assert_eq!((2, 3), (rotated.get_width(), rotated.get_height()));
+        assert_eq!(Some(&palette[..]), rotated.palette());
+        assert_eq!(8, rotated.dib_header.bits_per_pixel);
+        // The left column becomes the top row
+        assert_eq!(consts::BLUE, rotated.get_pixel(0, 0));
+        assert_eq!(BLACK, rotated.get_pixel(1, 0));
+        assert_eq!(RED, rotated.get_pixel(0, 1));
+        assert_eq!(BLACK, rotated.get_pixel(0, 2));
+        assert_eq!(consts::BLUE, rotated.get_pixel(1, 2));
+
+        assert_eq!(img, rotated.rotate90().rotate90().rotate90());
+        assert!(rotated.to_bytes_indexed(256).is_ok());
+    }
+
+    #[test]
+    fn pad_to_square_centered_keeps_square_images() {
+        let mut img = Image::new(3, 3);
+        img.set_pixel(1, 2, RED);
+        assert!(img.assert_square().is_ok());
+        assert_eq!(img, img.pad_to_square_centered(consts::WHITE));
+    }
+
+    #[test]
+    fn pad_to_square_centered_centers_vertically() {
+        let mut img = Image::new(6, 2);
+        img.fill_rect(0, 0, 6, 2, RED);
+        assert!(img.assert_square().is_err());
+
+        let square = img.pad_to_square_centered(consts::WHITE);
+        assert!(square.assert_square().is_ok());
+        assert_eq!(6, square.get_width());
+        for (x, y) in square.coordinates() {
+            let expected = if y == 2 || y == 3 { RED } else { consts::WHITE };
+            assert_eq!(expected, square.get_pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn padding_of_24_bit_rows() {
+        let expected = [1, 2, 3, 0, 1, 2, 3, 0];
+        for (width, &padding) in (1..9).zip(expected.iter()) {
+            assert_eq!(padding, Image::new(width, 2).padding, "width {}", width);
+            let decoded = from_bytes(&Image::new(width, 2).to_bytes()).unwrap();
+            assert_eq!(padding, decoded.padding, "width {}", width);
+            assert_eq!(padding, decoded.row_stride() - 3 * width);
+        }
+        // Indexed images keep their bit depth, so their rows are padded differently
+        let indexed = from_bytes(&indexed_bmp(3, 2, 8, &[BLACK], &[0; 6])).unwrap();
+        assert_eq!(1, indexed.padding);
+        assert_eq!(2, indexed.rotate90().padding);
+    }
+
+    #[test]
+    fn row_stride_includes_padding() {
+        // 24 bits per pixel
+        assert_eq!(12, Image::new(4, 3).row_stride());
+        assert_eq!(36, Image::new(4, 3).data_len_bytes());
+        assert_eq!(16, Image::new(5, 3).row_stride());
+        assert_eq!(48, Image::new(5, 3).data_len_bytes());
+
+        // 8 bits per pixel
+        let four = from_bytes(&indexed_bmp(4, 2, 8, &[BLACK], &[0; 8])).unwrap();
+        assert_eq!(4, four.row_stride());
+        assert_eq!(8, four.data_len_bytes());
+        let five = from_bytes(&indexed_bmp(5, 2, 8, &[BLACK], &[0; 10])).unwrap();
+        assert_eq!(8, five.row_stride());
+        assert_eq!(16, five.data_len_bytes());
+        // The pixel array of a file has the same size
+        assert_eq!(five.data_len_bytes(), five.dib_header.data_size);
+    }
+
+    #[test]
+    fn crop_copies_the_region() {
+        let mut img = Image::new(6, 4);
+        img.fill_rect(2, 1, 3, 2, RED);
+        let cropped = img.crop(Rect::new(1, 1, 4, 3)).unwrap();
+        assert_eq!((4, 3), (cropped.get_width(), cropped.get_height()));
+        for (x, y) in cropped.coordinates() {
+            assert_eq!(img.get_pixel(x + 1, y + 1), cropped.get_pixel(x, y));
+        }
+        assert_eq!(img, img.crop(Rect::new(0, 0, 6, 4)).unwrap());
+    }
+
+    #[test]
+    fn crop_rejects_regions_outside_the_image() {
+        let img = Image::new(6, 4);
+        let regions = [Rect::new(3, 0, 4, 1), Rect::new(0, 4, 1, 1), Rect::new(1, 1, u32::MAX, 1)];
+        for &rect in regions.iter() {
+            assert_eq!(BmpErrorKind::OutOfBounds, img.crop(rect).unwrap_err().kind);
+        }
+    }
+
+    #[test]
+    fn region_is_uniform_within_tolerance() {
+        let mut img = Image::new(8, 8);
+        img.fill_rect(1, 1, 4, 3, px!(100, 100, 100));
+        img.set_pixel(2, 2, px!(103, 98, 100));
+        let flat = Rect::new(1, 1, 4, 3);
+        // The red channel varies by 3
+        assert_eq!(Some(px!(100, 100, 100)), img.region_is_uniform(flat, 3).unwrap());
+        assert_eq!(None, img.region_is_uniform(flat, 2).unwrap());
+        // The region overlaps the black background
+        assert_eq!(None, img.region_is_uniform(Rect::new(0, 0, 3, 3), 50).unwrap());
+        assert!(img.region_is_uniform(Rect::new(6, 6, 3, 1), 0).is_err());
+    }
+
+    #[test]
+    fn scanlines_darken_odd_rows() {
+        let mut img = Image::new(3, 5);
+        img.fill_rect(0, 0, 3, 5, px!(100, 30, 200));
+        let crt = img.scanlines(40);
+        for (x, y) in crt.coordinates() {
+            let expected = if y % 2 == 0 { px!(100, 30, 200) } else { px!(60, 0, 160) };
+            assert_eq!(expected, crt.get_pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn monochrome_accent_keeps_colors_near_the_accent() {
+        let mut img = Image::new(4, 2);
+        img.fill_rect(0, 0, 2, 2, px!(250, 10, 5));
+        img.fill_rect(2, 0, 2, 2, consts::BLUE);
+        let splash = img.monochrome_accent(RED, 20);
+        for (x, y) in splash.coordinates() {
+            let expected = if x < 2 { px!(250, 10, 5) } else { px!(29) };
+            assert_eq!(expected, splash.get_pixel(x, y));
+        }
+        // A tighter tolerance turns the reds gray too
+        let luma = px!(250, 10, 5).luminance();
+        assert_eq!(px!(luma), img.monochrome_accent(RED, 10).get_pixel(0, 0));
+    }
+
+    #[test]
+    fn rgb_bytes_round_trip() {
+        let mut img = Image::new(3, 2);
+        img.map_pixels(|x, y, _| px!(x, y, 10 * x + y));
+        let bytes = img.as_rgb_bytes();
+        assert_eq!(&[1, 0, 10][..], &bytes[3..6]);
+        assert_eq!(&[0, 1, 1][..], &bytes[9..12]);
+        assert_eq!(img, Image::from_rgb_bytes(3, 2, &bytes).unwrap());
+    }
+
+    #[test]
+    fn ppm_header_and_pixels() {
+        let mut img = Image::new(12, 5);
+        img.map_pixels(|x, y, _| px!(x, y, x + y));
+        let ppm = img.to_ppm();
+        let header = b"P6\n12 5\n255\n";
+        assert_eq!(&header[..], &ppm[..header.len()]);
+        assert_eq!(img.as_rgb_bytes(), &ppm[header.len()..]);
+    }
+
+    #[test]
+    fn from_rgb_bytes_rejects_wrong_lengths() {
+        for &len in [0, 17, 19].iter() {
+            let err = Image::from_rgb_bytes(3, 2, &[0; 19][..len]).unwrap_err();
+            assert_eq!(BmpErrorKind::DimensionMismatch, err.kind);
+        }
+    }
+
+    #[test]
+    fn sub_image_reads_the_parent() {
+        let mut img = Image::new(6, 4);
+        img.map_pixels(|x, y, _| px!(x, y, 0));
+        let view = img.sub_image(2, 1, 3, 2).unwrap();
+        assert_eq!((3, 2), (view.get_width(), view.get_height()));
+        let cropped = img.crop(view.rect()).unwrap();
+        for (x, y) in view.coordinates() {
+            assert_eq!(cropped.get_pixel(x, y), view.get_pixel(x, y));
+        }
+        assert_eq!(&[px!(2, 2, 0), px!(3, 2, 0), px!(4, 2, 0)][..], view.row(1));
+
+        let regions = [(4, 0, 3, 1), (0, 3, 1, 2), (1, 1, u32::MAX, 1)];
+        for &(x, y, w, h) in regions.iter() {
+            assert_eq!(BmpErrorKind::OutOfBounds, img.sub_image(x, y, w, h).err().unwrap().kind);
+        }
+    }
+
+    #[test]
+    fn eq_pixels_ignores_headers() {
+        let decoded = from_bytes(&indexed_bmp(2, 2, 1, &[BLACK, RED], &[0, 1, 1, 0])).unwrap();
+        let mut drawn = Image::new(2, 2);
+        drawn.set_pixel(1, 0, RED);
+        drawn.set_pixel(0, 1, RED);
+        assert!(decoded != drawn);
+        assert!(decoded.eq_pixels(&drawn));
+        assert!(drawn.eq_pixels(&decoded));
+
+        drawn.set_pixel(0, 0, RED);
+        assert!(!decoded.eq_pixels(&drawn));
+        assert!(!Image::new(4, 1).eq_pixels(&Image::new(2, 2)));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn save_and_open_a_file() {
+        let mut path = std::env::temp_dir();
+        path.push(alloc::format!("bmp-save-and-open-{}.bmp", std::process::id()));
+        let mut img = Image::new(5, 3);
+        img.set_pixel(4, 2, RED);
+        img.save(&path).unwrap();
+        let opened = Image::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(img, opened.unwrap());
+
+        assert_eq!(BmpErrorKind::Io, Image::open(&path).unwrap_err().kind);
+    }
+
+    #[test]
+    fn dither_floyd_steinberg_follows_a_gradient() {
+        let mut gradient = Image::new(32, 8);
+        gradient.map_pixels(|x, _, _| px!(x * 255 / 31));
+        let mut dithered = gradient.clone();
+        dithered.dither_floyd_steinberg(&[BLACK, consts::WHITE]);
+
+        // The same input always gives the same output
+        let mut again = gradient.clone();
+        again.dither_floyd_steinberg(&[BLACK, consts::WHITE]);
+        assert_eq!(dithered, again);
+
+        // Every block of 4 columns has about as much white as the gradient has light, where
+        // quantizing would make the left half black and the right half white
+        assert!(dithered.data.iter().all(|&px| px == BLACK || px == consts::WHITE));
+        for block in 0..8 {
+            let (mut whites, mut light) = (0, 0);
+            for (x, y) in dithered.coordinates().filter(|&(x, _)| x / 4 == block) {
+                whites += (dithered.get_pixel(x, y) == consts::WHITE) as u32;
+                light += gradient.get_pixel(x, y).r as u32;
+            }
+            let expected = (light + 127) / 255;
+            assert!(whites + 2 >= expected && whites <= expected + 2, "{}: {}", block, whites);
+        }
+    }
+
+    #[test]
+    fn trim_border_crops_to_the_content() {
+        let white = consts::WHITE;
+        let mut img = Image::new(9, 7);
+        img.fill_rect(0, 0, 9, 7, white);
+        img.fill_rect(3, 2, 3, 2, RED);
+        img.set_pixel(4, 4, BLACK);
+        let trimmed = img.trim_border(white);
+        assert_eq!(img.crop(Rect::new(3, 2, 3, 3)).unwrap(), trimmed);
+        // Nothing else is trimmed away
+        assert_eq!(trimmed, trimmed.trim_border(white));
+
+        let mut blank = Image::new(5, 5);
+        blank.fill_rect(0, 0, 5, 5, white);
+        let trimmed = blank.trim_border(white);
+        assert_eq!((1, 1), (trimmed.get_width(), trimmed.get_height()));
+        assert_eq!(white, trimmed.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn count_and_unique_colors_of_a_two_color_image() {
+        let mut img = Image::new(6, 4);
+        img.fill_rect(0, 0, 6, 4, consts::WHITE);
+        img.fill_rect(1, 1, 2, 3, RED);
+        assert_eq!(6, img.count_color(RED));
+        assert_eq!(18, img.count_color(consts::WHITE));
+        assert_eq!(0, img.count_color(BLACK));
+        assert_eq!(2, img.unique_colors());
+        assert_eq!(0, Image::new(0, 0).unique_colors());
+    }
+
+    #[test]
+    fn rows_are_top_down() {
+        let mut img = Image::new(3, 4);
+        img.set_pixel(0, 0, RED);
+        img.set_pixel(2, 3, consts::WHITE);
+        assert_eq!(4, img.rows().count());
+        let first = img.rows().next().unwrap();
+        for x in 0..3 {
+            assert_eq!(img.get_pixel(x, 0), first[x as usize]);
+        }
+        assert_eq!(consts::WHITE, img.rows().last().unwrap()[2]);
+    }
+
+    #[test]
+    fn palette_nearest_color() {
+        let palette = Palette::new(alloc::vec![BLACK, RED, consts::BLUE, RED]);
+        assert_eq!(0, palette.nearest(px!(20, 10, 30)));
+        assert_eq!(1, palette.nearest(px!(200, 0, 90)));
+        assert_eq!(2, palette.nearest(px!(90, 0, 200)));
+        // The duplicate red is never picked, ties go to the lowest index
+        assert_eq!(1, palette.nearest(RED));
+        assert_eq!(0, palette.nearest(px!(64, 0, 0)));
+    }
+
+    #[test]
+    fn built_in_palettes() {
+        let gray = Palette::grayscale(4);
+        assert_eq!(&[px!(0), px!(85), px!(170), px!(255)][..], gray.colors());
+        assert_eq!(&[BLACK][..], Palette::grayscale(1).colors());
+        assert_eq!(256, Palette::grayscale(256).len());
+        assert_eq!(px!(128), Palette::grayscale(256).colors()[128]);
+
+        let web = Palette::web_safe();
+        assert_eq!(216, web.len());
+        assert_eq!(BLACK, web.colors()[0]);
+        assert_eq!(consts::WHITE, web.colors()[215]);
+        assert_eq!(px!(0, 0, 51), web.colors()[1]);
+        assert_eq!(px!(51, 0, 0), web.colors()[36]);
+        assert_eq!(web.colors()[web.nearest(px!(60, 110, 140))], px!(51, 102, 153));
+    }
+
+    #[test]
+    fn apply_identity_and_inversion_luts() {
+        let mut img = Image::new(16, 16);
+        img.map_pixels(|x, y, _| px!(x * 16 + y, 255 - x * 16, y * 3));
+        let original = img.clone();
+
+        let mut identity = [0; 256];
+        let mut invert = [0; 256];
+        for i in 0..256 {
+            identity[i] = i as u8;
+            invert[i] = 255 - i as u8;
+        }
+        img.apply_lut(&identity, &identity, &identity);
+        assert_eq!(original, img);
+        assert_eq!(identity, gamma_lut(1.0));
+
+        img.apply_lut(&invert, &identity, &identity);
+        for (x, y) in img.coordinates() {
+            let (px, before) = (img.get_pixel(x, y), original.get_pixel(x, y));
+            assert_eq!(px!(255 - before.r, before.g, before.b), px);
+        }
+    }
+
+    #[test]
+    fn gamma_lut_values() {
+        // Rounded values of 255 * (i / 255)^gamma
+        let samples = [1, 10, 64, 128, 200, 254];
+        let expected = [
+            (0.45, [21, 59, 137, 187, 229, 255]),
+            (2.2, [0, 0, 12, 56, 149, 253]),
+            (3.0, [0, 0, 4, 32, 123, 252]),
+        ];
+        for &(gamma, values) in expected.iter() {
+            let lut = gamma_lut(gamma);
+            assert_eq!((0, 255), (lut[0], lut[255]));
+            for (&i, &v) in samples.iter().zip(values.iter()) {
+                assert_eq!(v, lut[i], "gamma {} at {}", gamma, i);
+            }
+            assert!(lut.windows(2).all(|w| w[0] <= w[1]));
+        }
+    }
+
+    #[test]
+    fn mean_and_dominant_color_of_two_colors() {
+        let mut img = Image::new(4, 4);
+        img.fill_rect(0, 0, 4, 3, RED);
+        assert_eq!(RED, img.dominant_color());
+        // Red is 12 * 255 / 16 = 191.25 on average
+        assert_eq!(px!(191, 0, 0), img.mean_color());
+
+        // Eight pixels of each color, the lower packed value wins
+        let mut tied = Image::new(4, 4);
+        tied.fill_rect(0, 0, 4, 2, consts::BLUE);
+        assert_eq!(BLACK, tied.dominant_color());
+        tied.fill_rect(0, 2, 4, 2, consts::WHITE);
+        assert_eq!(consts::BLUE, tied.dominant_color());
+        assert_eq!(px!(128, 128, 255), tied.mean_color());
+
+        assert_eq!(BLACK, Image::new(0, 0).mean_color());
+        assert_eq!(BLACK, Image::new(0, 0).dominant_color());
+    }
+
+    #[test]
+    fn new_filled_sets_every_pixel() {
+        let color = px!(12, 34, 56);
+        let img = Image::new_filled(5, 3, color);
+        assert_eq!((5, 3), (img.get_width(), img.get_height()));
+        assert!(img.coordinates().all(|(x, y)| img.get_pixel(x, y) == color));
+        assert_eq!(15, img.count_color(color));
+        assert_eq!(Image::new(5, 3).to_bytes().len(), img.to_bytes().len());
+        assert_eq!(Image::new(2, 2), Image::new_filled(2, 2, BLACK));
+    }
+
+    #[test]
+    fn test_patterns() {
+        let white = consts::WHITE;
+        let board = Image::checkerboard(7, 5, RED, white, 3);
+        assert_eq!(RED, board.get_pixel(0, 0));
+        assert_eq!(RED, board.get_pixel(2, 2));
+        assert_eq!(white, board.get_pixel(3, 0));
+        assert_eq!(white, board.get_pixel(0, 4));
+        assert_eq!(RED, board.get_pixel(6, 0));
+        assert_eq!(white, board.get_pixel(6, 4));
+
+        let gradient = Image::gradient_horizontal(4, 3, px!(0, 255, 10), px!(255, 0, 10));
+        assert_eq!(px!(0, 255, 10), gradient.get_pixel(0, 2));
+        assert_eq!(px!(85, 170, 10), gradient.get_pixel(1, 0));
+        assert_eq!(px!(170, 85, 10), gradient.get_pixel(2, 1));
+        assert_eq!(px!(255, 0, 10), gradient.get_pixel(3, 2));
+        assert_eq!(RED, Image::gradient_horizontal(1, 1, RED, white).get_pixel(0, 0));
+
+        let solid = Image::solid(3, 2, RED);
+        assert_eq!(6, solid.count_color(RED));
+        assert_eq!(RED, solid.get_pixel(2, 1));
+    }
+
+    #[test]
+    fn try_from_bytes() {
+        use core::convert::TryInto;
+
+        let mut img = Image::new(3, 2);
+        img.set_pixel(2, 1, RED);
+        let bytes = img.to_bytes();
+        assert_eq!(img, Image::try_from(&bytes[..]).unwrap());
+        let converted: BmpResult<Image> = bytes[..20].try_into();
+        assert_eq!(BmpErrorKind::Truncated, converted.unwrap_err().kind);
+    }
+
+    #[test]
+    fn get_pixel_checked_bounds() {
+        let mut img = Image::new(3, 2);
+        img.set_pixel(2, 1, RED);
+        assert_eq!(Some(RED), img.get_pixel_checked(2, 1));
+        assert_eq!(Some(BLACK), img.get_pixel_checked(0, 0));
+        assert_eq!(None, img.get_pixel_checked(3, 1));
+        assert_eq!(None, img.get_pixel_checked(2, 2));
+        assert_eq!(None, img.get_pixel_checked(u32::MAX, u32::MAX));
+        assert_eq!(None, Image::new(0, 0).get_pixel_checked(0, 0));
+    }
+
+    #[test]
+    fn downscale_box_averages_blocks() {
+        // The left half is white, and the top right block has a single red pixel
+        let mut img = Image::new(4, 4);
+        img.fill_rect(0, 0, 2, 4, consts::WHITE);
+        img.set_pixel(3, 0, RED);
+
+        let small = img.downscale_box(2, 2);
+        assert_eq!((2, 2), (small.get_width(), small.get_height()));
+        assert_eq!(consts::WHITE, small.get_pixel(0, 0));
+        assert_eq!(consts::WHITE, small.get_pixel(0, 1));
+        assert_eq!(px!(64, 0, 0), small.get_pixel(1, 0));
+        assert_eq!(BLACK, small.get_pixel(1, 1));
+
+        // Blocks of 1 and 2 columns when going from 3 columns to 2
+        let mut row = Image::new(3, 1);
+        row.set_pixel(0, 0, consts::WHITE);
+        row.set_pixel(2, 0, RED);
+        let uneven = row.downscale_box(2, 1);
+        assert_eq!(consts::WHITE, uneven.get_pixel(0, 0));
+        assert_eq!(px!(128, 0, 0), uneven.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn pad_adds_a_border() {
+        let mut img = Image::new(2, 2);
+        img.set_pixel(1, 0, RED);
+        let white = consts::WHITE;
+
+        let padded = img.pad(1, 1, 1, 1, white);
+        assert_eq!((4, 4), (padded.get_width(), padded.get_height()));
+        for (x, y) in padded.coordinates() {
+            let inside = (1..3).contains(&x) && (1..3).contains(&y);
+            let expected = if inside { img.get_pixel(x - 1, y - 1) } else { white };
+            assert_eq!(expected, padded.get_pixel(x, y), "({}, {})", x, y);
+        }
+        assert_eq!(img, padded.trim_border(white));
+        assert_eq!(padded.to_bytes().len(), Image::new(4, 4).to_bytes().len());
+    }
+
+    #[test]
+    fn transpose_swaps_rows_and_columns() {
+        let mut img = Image::new(4, 2);
+        img.set_pixel(3, 0, RED);
+        img.set_pixel(1, 1, consts::WHITE);
+        img.set_pixel(0, 1, consts::BLUE);
+
+        let transposed = img.transpose();
+        assert_eq!((2, 4), (transposed.get_width(), transposed.get_height()));
+        assert_eq!(RED, transposed.get_pixel(0, 3));
+        assert_eq!(consts::WHITE, transposed.get_pixel(1, 1));
+        assert_eq!(consts::BLUE, transposed.get_pixel(1, 0));
+        assert_eq!(BLACK, transposed.get_pixel(0, 0));
+        assert_eq!(img, transposed.transpose());
+        for (x, y) in img.coordinates() {
+            assert_eq!(img.get_pixel(x, y), transposed.get_pixel(y, x));
+        }
+    }
+
+    #[test]
+    fn new_images_are_allocated_once() {
+        let img = Image::checkerboard(7, 5, RED, BLACK, 2);
+        let exact = |copy: Image| {
+            assert_eq!(copy.data.len(), copy.data.capacity());
+            assert_eq!((copy.width * copy.height) as usize, copy.data.len());
+        };
+        exact(img.crop(Rect::new(1, 2, 4, 3)).unwrap());
+        exact(img.rotate90());
+        exact(img.transpose());
+        exact(img.pad(1, 2, 3, 4, RED));
+        exact(img.resize(10, 3, ResizeFilter::Bilinear));
+        exact(img.downscale_average_linear(3, 2));
+    }
+
+    #[test]
+    fn sample_bilinear_interpolates_between_centers() {
+        let mut img = Image::new(2, 2);
+        img.set_pixel(1, 0, consts::WHITE);
+        img.set_pixel(0, 1, RED);
+        // Pixel centers are exact, and positions past the edges are clamped
+        for (x, y) in img.coordinates() {
+            assert_eq!(img.get_pixel(x, y), img.sample_bilinear(x as f32, y as f32));
+            assert_eq!(img.get_pixel(x, y), img.sample_bilinear_q8(x * 256, y * 256));
+        }
+        assert_eq!(consts::WHITE, img.sample_bilinear(5.0, -1.0));
+        assert_eq!(consts::WHITE, img.sample_bilinear_q8(1280, 0));
+
+        // Midpoints average their neighbors, rounding half up
+        assert_eq!(px!(128, 128, 128), img.sample_bilinear(0.5, 0.0));
+        assert_eq!(px!(128, 128, 128), img.sample_bilinear_q8(128, 0));
+        assert_eq!(px!(128, 0, 0), img.sample_bilinear(0.0, 0.5));
+        assert_eq!(px!(128, 0, 0), img.sample_bilinear_q8(0, 128));
+        assert_eq!(px!(128, 64, 64), img.sample_bilinear(0.5, 0.5));
+        assert_eq!(px!(128, 64, 64), img.sample_bilinear_q8(128, 128));
+    }
+
+    #[test]
+    fn iter_window_clamps_the_neighborhood() {
+        let mut img = Image::new(3, 2);
+        for (x, y) in img.coordinates() {
+            img.set_pixel(x, y, px!(x + 3 * y, 0, 0));
+        }
+        // The red channel is 0 1 2 on the top row and 3 4 5 on the bottom one
+        let sums: Vec<u32> = img
+            .iter_window(1)
+            .map(|(_, _, window)| window.pixels().map(|px| px.r as u32).sum())
+            .collect();
+        assert_eq!(alloc::vec![12, 18, 24, 21, 27, 33], sums);
+
+        let windows: Vec<_> = img.iter_window(2).collect();
+        assert_eq!(6, windows.len());
+        let (x, y, window) = windows[4];
+        assert_eq!((1, 1, 2), (x, y, window.radius()));
+        assert_eq!(px!(4, 0, 0), window.center());
+        assert_eq!(px!(0, 0, 0), window.get(-2, -2));
+        assert_eq!(px!(5, 0, 0), window.get(2, 1));
+        assert_eq!(25, window.pixels().count());
+    }
+
+    #[test]
+    fn median3x3_removes_a_noisy_pixel() {
+        let mut img = Image::new(6, 4);
+        img.fill_rect(3, 0, 3, 4, consts::WHITE);
+        let clean = img.clone();
+        img.set_pixel(1, 2, RED);
+        img.set_pixel(5, 3, BLACK);
+
+        // The noise is removed, and the edge between black and white is kept
+        assert_eq!(clean, img.median3x3());
+    }
+
+    #[test]
+    fn equalize_histogram_spans_the_full_range() {
+        let mut img = Image::gradient_horizontal(64, 2, px!(100, 100, 100), px!(120, 120, 120));
+        img.set_pixel(0, 1, px!(110, 100, 100));
+        img.equalize_histogram();
+        let luma = |px: Pixel| px.luminance();
+        assert_eq!(0, img.data.iter().map(|&px| luma(px)).min().unwrap());
+        assert_eq!(255, img.data.iter().map(|&px| luma(px)).max().unwrap());
+        // The mapping is monotonic, and chroma is kept
+        for x in 1..64 {
+            assert!(luma(img.get_pixel(x - 1, 0)) <= luma(img.get_pixel(x, 0)));
+        }
+        let tinted = img.get_pixel(0, 1);
+        assert_eq!((10, 0), (tinted.r - tinted.g, tinted.g - tinted.b));
+
+        let mut flat = Image::new_filled(3, 3, px!(50, 60, 70));
+        flat.equalize_histogram();
+        assert_eq!(Image::new_filled(3, 3, px!(50, 60, 70)), flat);
+    }
+
+    #[test]
+    #[should_panic]
+    fn pad_rejects_overflowing_dimensions() {
+        Image::new(2, 2).pad(0, 0, u32::MAX, 0, RED);
+    }
+
+    #[test]
+    fn draw_boxes_outlines_each_box() {
+        let mut img = Image::new(12, 6);
+        img.draw_boxes(&[
+            (Rect::new(0, 0, 4, 4), RED),
+            (Rect::new(7, 2, 10, 10), consts::BLUE),
+        ]);
+        assert_eq!(RED, img.get_pixel(3, 0));
+        assert_eq!(RED, img.get_pixel(0, 3));
+        assert_eq!(RED, img.get_pixel(3, 3));
+        assert_eq!(BLACK, img.get_pixel(1, 1));
+        // The second box is clipped at the right and bottom edges
+        assert_eq!(consts::BLUE, img.get_pixel(7, 2));
+        assert_eq!(consts::BLUE, img.get_pixel(11, 2));
+        assert_eq!(consts::BLUE, img.get_pixel(7, 5));
+        assert_eq!(BLACK, img.get_pixel(11, 5));
+        // Nothing is drawn between the boxes
+        for x in 4..7 {
+            for y in 0..6 {
+                assert_eq!(BLACK, img.get_pixel(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn fill_rect_colors_the_interior() {
+        let mut img = Image::new(6, 6);
+        img.fill_rect(1, 1, 4, 4, RED);
+        for (x, y) in img.coordinates() {
+            let inside = x >= 1 && x < 5 && y >= 1 && y < 5;
+            assert_eq!(if inside { RED } else { BLACK }, img.get_pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn rects_are_clipped_to_the_image() {
+        let mut img = Image::new(4, 4);
+        img.fill_rect(2, 2, 10, 10, RED);
+        img.draw_rect(3, 3, 100, 100, RED);
+        img.fill_rect(10, 10, 2, 2, RED);
+        assert_eq!(RED, img.get_pixel(3, 3));
+        assert_eq!(BLACK, img.get_pixel(1, 1));
+    }
+
+    #[test]
+    fn empty_rects_are_no_ops() {
+        let mut img = Image::new(4, 4);
+        img.draw_rect(1, 1, 0, 3, RED);
+        img.fill_rect(1, 1, 3, 0, RED);
+        assert!(img.coordinates().all(|(x, y)| img.get_pixel(x, y) == BLACK));
+    }
+
+    #[test]
+    fn carve_width_preserves_high_energy_content() {
+        let gray = px!(128, 128, 128);
+        let mut img = Image::new(12, 6);
+        img.fill_rect(0, 0, 12, 6, gray);
+        // A checkered object in columns 7..11
+        for y in 0..6 {
+            for x in 7..11 {
+                let c = if (x + y) % 2 == 0 { consts::WHITE } else { BLACK };
+                img.set_pixel(x, y, c);
+            }
+        }
+
+        let carved = img.carve_width(8);
+        assert_eq!(8, carved.get_width());
+        assert_eq!(6, carved.get_height());
+        let object = carved.coordinates().filter(|&(x, y)| carved.get_pixel(x, y) != gray).count();
+        let background = carved.coordinates().count() - object;
+        assert_eq!(24, object);
+        assert_eq!(24, background);
+    }
+
+    #[test]
+    fn carve_width_is_a_no_op_for_narrow_targets() {
+        let mut img = Image::new(3, 2);
+        img.set_pixel(1, 1, RED);
+        assert!(img.carve_width(3) == img);
+        assert!(img.carve_width(5) == img);
+    }
+
+    #[test]
+    fn blit_clips_at_the_edge() {
+        let mut src = Image::new(2, 2);
+        src.fill_rect(0, 0, 2, 2, RED);
+        let mut img = Image::new(4, 4);
+        img.blit(&src, 3, 2);
+        for (x, y) in img.coordinates() {
+            let covered = x == 3 && y >= 2;
+            assert_eq!(if covered { RED } else { BLACK }, img.get_pixel(x, y));
+        }
+    }
+
+    #[test]
+    fn blit_off_canvas_is_a_no_op() {
+        let src = Image::new(2, 2);
+        let mut img = Image::new(4, 4);
+        img.fill_rect(0, 0, 4, 4, RED);
+        img.blit(&src, 4, 0);
+        img.blit(&src, u32::max_value(), u32::max_value());
+        assert!(img.coordinates().all(|(x, y)| img.get_pixel(x, y) == RED));
+    }
+
+    #[test]
+    fn has_at_most_colors_on_a_photo_like_image() {
+        let mut img = Image::new(64, 64);
+        for (x, y) in img.coordinates() {
+            img.set_pixel(x, y, px!(x * 4, y * 4, x + y));
+        }
+        assert!(!img.has_at_most_colors(0));
+        assert!(!img.has_at_most_colors(256));
+        assert!(img.has_at_most_colors(64 * 64));
+
+        // Every pixel has a new color, so the scan stops right after the first `n + 1` pixels
+        assert_eq!((false, 1), img.scan_colors(0));
+        assert_eq!((false, 257), img.scan_colors(256));
+        assert_eq!((true, 64 * 64), img.scan_colors(64 * 64));
+    }
+
+    #[test]
+    fn has_at_most_colors_on_a_four_color_image() {
+        let mut img = Image::new(4, 4);
+        img.fill_rect(0, 0, 2, 2, RED);
+        img.fill_rect(2, 0, 2, 2, consts::BLUE);
+        img.fill_rect(0, 2, 2, 2, consts::WHITE);
+        assert!(img.has_at_most_colors(4));
+        assert!(img.has_at_most_colors(5));
+        assert!(!img.has_at_most_colors(3));
+    }
+
+    #[test]
+    fn histogram_of_a_two_color_image() {
+        let mut img = Image::new(4, 2);
+        img.fill_rect(0, 0, 1, 2, px!(10, 20, 30));
+        let hist = img.histogram();
+        assert_eq!(2, hist[0][10]);
+        assert_eq!(2, hist[1][20]);
+        assert_eq!(2, hist[2][30]);
+        for channel in hist.iter() {
+            assert_eq!(6, channel[0]);
+            assert_eq!(8, channel.iter().sum::<u32>());
+        }
+    }
+
+    #[test]
+    fn changed_region_bounds_a_changed_block() {
+        let img = Image::new(8, 8);
+        let mut edited = img.clone();
+        edited.fill_rect(2, 3, 4, 2, px!(0, 0, 5));
+        edited.set_pixel(7, 7, px!(2, 2, 2));
+
+        assert_eq!(None, img.changed_region(&img, 0).unwrap());
+        assert_eq!(Some(Rect::new(2, 3, 6, 5)), img.changed_region(&edited, 1).unwrap());
+        assert_eq!(Some(Rect::new(2, 3, 4, 2)), img.changed_region(&edited, 2).unwrap());
+        assert_eq!(None, img.changed_region(&edited, 5).unwrap());
+    }
+
+    #[test]
+    fn changed_region_rejects_mismatched_dimensions() {
+        let err = Image::new(2, 3).changed_region(&Image::new(3, 2), 0).unwrap_err();
+        assert_eq!(BmpErrorKind::DimensionMismatch, err.kind);
+    }
+
+    #[test]
+    fn resize_nearest_repeats_pixels() {
+        let mut img = Image::new(2, 2);
+        img.set_pixel(1, 0, RED);
+        img.set_pixel(0, 1, consts::WHITE);
+        let big = img.resize(4, 4, ResizeFilter::Nearest);
+        for (x, y) in big.coordinates() {
+            assert_eq!(img.get_pixel(x / 2, y / 2), big.get_pixel(x, y));
+        }
+        assert_eq!(big, img.resize_nearest(4, 4));
+    }
+
+    #[test]
+    fn resize_bilinear_interpolates_between_centers() {
+        let mut img = Image::new(2, 1);
+        img.set_pixel(1, 0, consts::WHITE);
+        let big = img.resize(4, 1, ResizeFilter::Bilinear);
+        let values: Vec<u8> = (0..4).map(|x| big.get_pixel(x, 0).r).collect();
+        assert_eq!(&[0, 64, 191, 255][..], &values[..]);
+        assert_eq!(big, img.resize_bilinear(4, 1));
+    }
+
+    #[test]
+    fn resize_area_averages_blocks() {
+        let mut img = Image::new(4, 1);
+        for x in 0..4 {
+            img.set_pixel(x, 0, px!(10 * (x + 1), 0, 255));
+        }
+        let small = img.resize(2, 1, ResizeFilter::Area);
+        assert_eq!(px!(15, 0, 255), small.get_pixel(0, 0));
+        assert_eq!(px!(35, 0, 255), small.get_pixel(1, 0));
+        assert_eq!(small, img.resize_area(2, 1));
+        assert_eq!(Image::new(3, 2), Image::new(0, 0).resize(3, 2, ResizeFilter::Area));
+    }
+
+    #[test]
+    fn resize_to_and_from_single_pixel_dimensions() {
+        let filters = [ResizeFilter::Nearest, ResizeFilter::Bilinear, ResizeFilter::Area];
+        let mut img = Image::new(4, 3);
+        img.map_pixels(|x, y, _| px!(60 * x, 100 * y, 0));
+
+        // Into a single column, the red channel is sampled at or averaged around the center
+        for (&filter, &r) in filters.iter().zip([120, 90, 90].iter()) {
+            let column = img.resize(1, 3, filter);
+            for y in 0..3 {
+                assert_eq!(px!(r, 100 * y, 0), column.get_pixel(0, y), "{:?}", filter);
+            }
+        }
+        // Into a single row, every filter lands on the middle row
+        for &filter in filters.iter() {
+            let row = img.resize(4, 1, filter);
+            for x in 0..4 {
+                assert_eq!(px!(60 * x, 100, 0), row.get_pixel(x, 0), "{:?}", filter);
+            }
+        }
+
+        // A single row or column is repeated when upscaling, and a lone pixel fills the image
+        let mut single = Image::new(1, 1);
+        single.set_pixel(0, 0, RED);
+        let flat = |img: &Image, color: Pixel| img.data.iter().all(|&px| px == color);
+        for &filter in filters.iter() {
+            assert!(flat(&single.resize(3, 2, filter), RED), "{:?}", filter);
+            let tall = img.resize(1, 3, filter).resize(5, 3, filter);
+            for (x, y) in tall.coordinates() {
+                assert_eq!(tall.get_pixel(0, y), tall.get_pixel(x, y), "{:?}", filter);
+            }
+        }
+        assert!(flat(&single.downscale_average_linear(1, 1), RED));
+        let column = img.downscale_average_linear(1, 3);
+        for y in 0..3 {
+            assert_eq!(100 * y as u8, column.get_pixel(0, y).g);
+        }
+    }
+
+    #[test]
+    fn downscale_average_linear_is_brighter_than_srgb_averaging() {
+        let mut img = Image::new(4, 4);
+        for (x, y) in img.coordinates() {
+            if (x + y) % 2 == 0 {
+                img.set_pixel(x, y, RED);
+            }
+        }
+        let small = img.downscale_average_linear(2, 2);
+        assert_eq!((2, 2), (small.get_width(), small.get_height()));
+        for (x, y) in small.coordinates() {
+            let px = small.get_pixel(x, y);
+            // Naive averaging of 255 and 0 would give 128
+            assert!(px.r > 128);
+            assert_eq!(px!(188, 0, 0), px);
+        }
+
+        // Uniform images keep their color
+        let mut gray = Image::new(3, 3);
+        gray.fill_rect(0, 0, 3, 3, px!(77, 77, 77));
+        assert_eq!(px!(77, 77, 77), gray.downscale_average_linear(1, 1).get_pixel(0, 0));
+    }
+
+    #[test]
+    fn map_pixels_builds_a_red_gradient() {
+        let mut img = Image::new(8, 3);
+        let mut visited = 0;
+        img.map_pixels(|x, _, px| {
+            visited += 1;
+            px + px!(x * 32, 0, 0)
+        });
+        assert_eq!(24, visited);
+        for y in 0..3 {
+            assert_eq!(px!(64, 0, 0), img.get_pixel(2, y));
+            assert_eq!(px!(224, 0, 0), img.get_pixel(7, y));
+        }
+
+        img.map_pixels(|x, y, px| if y == 0 && x == 0 { consts::WHITE } else { px });
+        assert_eq!(consts::WHITE, img.get_pixel(0, 0));
+        assert_eq!(BLACK, img.get_pixel(0, 2));
+    }
+
+    #[test]
+    fn quantize_to_palette_picks_the_nearest_color() {
+        let mut img = Image::new(5, 1);
+        let colors = [
+            px!(10, 20, 30),
+            px!(127, 128, 127),
+            px!(128, 127, 128),
+            RED,
+            px!(240, 5, 250),
+        ];
+        for (x, &px) in colors.iter().enumerate() {
+            img.set_pixel(x as u32, 0, px);
+        }
+        img.quantize_to_palette(&[BLACK, consts::WHITE]);
+        let expected = [BLACK, BLACK, consts::WHITE, BLACK, consts::WHITE];
+        for (x, &px) in expected.iter().enumerate() {
+            assert_eq!(px, img.get_pixel(x as u32, 0));
+        }
+
+        // Ties go to the first color, and an empty palette changes nothing
+        let mut tied = Image::new(1, 1);
+        tied.set_pixel(0, 0, px!(0, 255, 0));
+        let copy = tied.clone();
+        tied.quantize_to_palette(&[]);
+        assert_eq!(copy, tied);
+        tied.quantize_to_palette(&[RED, consts::BLUE]);
+        assert_eq!(RED, tied.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn quick_eq_agrees_with_comparing_pixels() {
+        let pixels_eq = |a: &Image, b: &Image| {
+            a.get_width() == b.get_width() && a.get_height() == b.get_height() && a.data == b.data
+        };
+        let mut img = Image::new(20, 20);
+        img.fill_rect(3, 3, 10, 4, RED);
+
+        let same = from_bytes(&img.to_bytes_indexed(256).unwrap()).unwrap();
+        let mut sampled = img.clone();
+        sampled.set_pixel(0, 19, consts::WHITE);
+        let mut unsampled = img.clone();
+        unsampled.set_pixel(1, 19, consts::WHITE);
+        let others = [same, sampled, unsampled, Image::new(20, 20), Image::new(40, 10)];
+
+        for other in others.iter() {
+            assert_eq!(pixels_eq(&img, other), img.quick_eq(other));
+        }
+        // Only the headers of the indexed copy differ
+        assert!(img.quick_eq(&others[0]));
+        assert!(!img.quick_eq(&others[2]));
+    }
+
+    #[test]
+    fn matches_template_ignores_the_writable_area() {
+        let mut template = Image::new(6, 4);
+        template.fill_rect(0, 0, 6, 1, consts::BLUE);
+        // Only the field below the title bar may be written to
+        let mut mask = Image::new(6, 4);
+        mask.fill_rect(0, 0, 6, 4, consts::WHITE);
+        mask.fill_rect(1, 2, 4, 1, BLACK);
+
+        let mut filled = template.clone();
+        filled.fill_rect(1, 2, 4, 1, RED);
+        filled.set_pixel(0, 3, px!(3, 0, 0));
+        assert!(filled.matches_template(&template, &mask, 3).unwrap());
+        assert!(!filled.matches_template(&template, &mask, 2).unwrap());
+
+        let mut tampered = filled.clone();
+        tampered.set_pixel(2, 0, consts::WHITE);
+        assert!(!tampered.matches_template(&template, &mask, 3).unwrap());
+        assert!(Image::new(6, 3).matches_template(&template, &mask, 0).is_err());
+    }
+
+    #[test]
+    fn stretch_percentile_ignores_outliers() {
+        let mut img = Image::new(10, 10);
+        img.fill_rect(0, 0, 10, 5, px!(100, 100, 100));
+        img.fill_rect(0, 5, 10, 5, px!(150, 150, 150));
+        img.set_pixel(3, 3, px!(125, 125, 125));
+        img.set_pixel(0, 0, BLACK);
+        img.set_pixel(1, 0, BLACK);
+        img.set_pixel(8, 9, consts::WHITE);
+        img.set_pixel(9, 9, consts::WHITE);
+
+        // The outliers span the full range, so only clipping them stretches the image
+        assert_eq!(img, img.stretch_percentile(0.0, 0.0));
+        let stretched = img.stretch_percentile(3.0, 3.0);
+        assert_eq!(BLACK, stretched.get_pixel(5, 2));
+        assert_eq!(px!(127, 127, 127), stretched.get_pixel(3, 3));
+        assert_eq!(consts::WHITE, stretched.get_pixel(5, 7));
+        assert_eq!(BLACK, stretched.get_pixel(0, 0));
+        assert_eq!(consts::WHITE, stretched.get_pixel(9, 9));
+    }
+
+    #[test]
+    fn threshold_splits_a_gradient() {
+        let mut img = Image::new(8, 1);
+        for x in 0..8 {
+            img.set_pixel(x, 0, px!(x * 32, x * 32, x * 32));
+        }
+        let binary = img.threshold(128);
+        for x in 0..8 {
+            let expected = if x >= 4 { consts::WHITE } else { BLACK };
+            assert_eq!(expected, binary.get_pixel(x, 0));
+        }
+    }
+
+    #[test]
+    fn radial_mask_falls_off_from_the_center() {
+        let mask = Image::radial_mask(9, 7, 200, 20);
+        assert_eq!(px!(200, 200, 200), mask.get_pixel(4, 3));
+        for &(x, y) in [(0, 0), (8, 0), (0, 6), (8, 6)].iter() {
+            assert_eq!(px!(20, 20, 20), mask.get_pixel(x, y));
+        }
+        let mid = mask.get_pixel(2, 3).r;
+        assert!(mid < 200 && mid > 20);
+        assert!(mask.get_pixel(1, 3).r < mid);
+    }
+
+    #[test]
+    fn isqrt_rounds_down() {
+        assert_eq!(0, isqrt(0));
+        assert_eq!(1, isqrt(3));
+        assert_eq!(2, isqrt(4));
+        assert_eq!(65535, isqrt(u32::max_value() as u64));
+    }
+
+    #[test]
+    fn debug_summarizes_the_image() {
+        let debug = alloc::format!("{:?}", Image::new(30, 20));
+        assert_eq!(
+            "Image { width: 30, height: 20, version: \"BMP Version 3\", data_len: 600 }",
+            debug
+        );
+    }
+
+    #[test]
+    fn palette_of_a_decoded_8bit_image() {
+        let palette = [RED, consts::GREEN, consts::BLUE];
+        let bytes = indexed_bmp(3, 2, 8, &palette, &[0, 1, 2, 2, 1, 0]);
+        let mut img = from_bytes(&bytes).unwrap();
+
+        assert_eq!(Some(&palette[..]), img.palette());
+        assert_eq!(consts::BLUE, img.get_pixel(2, 0));
+        assert_eq!(RED, img.get_pixel(2, 1));
+
+        img.set_palette(alloc::vec![BLACK; 256]).unwrap();
+        assert_eq!(256, img.palette().unwrap().len());
+        assert!(img.set_palette(alloc::vec![BLACK; 257]).is_err());
+        assert!(img.set_palette(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn set_palette_requires_an_indexed_image() {
+        let mut img = Image::new(2, 2);
+        assert_eq!(None, img.palette());
+        assert!(img.set_palette(alloc::vec![BLACK, RED]).is_err());
+    }
+
+    #[test]
+    fn planar_round_trip() {
+        let mut img = Image::new(3, 2);
+        for (x, y) in img.coordinates() {
+            img.set_pixel(x, y, px!(x, y, x * 10 + y));
+        }
+        let (r, g, b) = img.to_planar();
+        assert_eq!(&[0, 1, 2, 0, 1, 2], &r[..]);
+        assert_eq!(&[0, 0, 0, 1, 1, 1], &g[..]);
+        assert_eq!(img, Image::from_planar(3, 2, &r, &g, &b).unwrap());
+        assert!(Image::from_planar(3, 2, &r, &g, &b[1..]).is_err());
+        assert!(Image::from_planar(2, 2, &r, &g, &b).is_err());
+    }
+
+    #[test]
+    fn split_and_merge_channels() {
+        let mut img = Image::new(4, 3);
+        img.map_pixels(|x, y, _| px!(x * 50, y * 80, 255 - x));
+        let (r, g, b) = img.split_channels();
+        assert_eq!((r.clone(), g.clone(), b.clone()), img.to_planar());
+        assert_eq!(img, Image::merge_channels(4, 3, &r, &g, &b).unwrap());
+        let err = Image::merge_channels(4, 3, &r[1..], &g, &b).unwrap_err();
+        assert_eq!(BmpErrorKind::DimensionMismatch, err.kind);
+    }
+
+    #[test]
+    fn region_histograms_of_a_two_tone_image() {
+        let mut img = Image::new(6, 4);
+        img.fill_rect(3, 0, 3, 4, consts::WHITE);
+        let cells = img.region_histograms(2, 2);
+        assert_eq!(4, cells.len());
+        for row in 0..2 {
+            let (left, right) = (&cells[row * 2], &cells[row * 2 + 1]);
+            for channel in 0..3 {
+                assert_eq!(6, left[channel][0]);
+                assert_eq!(0, left[channel][255]);
+                assert_eq!(0, right[channel][0]);
+                assert_eq!(6, right[channel][255]);
+            }
+        }
+        assert!(img.region_histograms(0, 2).is_empty());
+        // A grid finer than the image has one cell per pixel
+        let cells = img.region_histograms(65536, 65536);
+        assert_eq!(24, cells.len());
+        assert!(cells.iter().all(|cell| cell[0].iter().sum::<u32>() == 1));
+    }
+
+    #[test]
+    fn clahe_increases_local_contrast_in_both_halves() {
+        let mut img = Image::new(16, 8);
+        for (x, y) in img.coordinates() {
+            let base = if x < 8 { 10 } else { 200 };
+            let v = base + (x + y) % 11;
+            img.set_pixel(x, y, px!(v, v, v));
+        }
+        let range = |img: &Image, xs: core::ops::Range<u32>| {
+            let values = || xs.clone().flat_map(|x| (0..8).map(move |y| (x, y)));
+            let max = values().map(|(x, y)| img.get_pixel(x, y).r).max().unwrap();
+            let min = values().map(|(x, y)| img.get_pixel(x, y).r).min().unwrap();
+            max - min
+        };
+
+        let enhanced = img.clahe(2, 1, 40.0);
+        assert_eq!(10, range(&img, 0..4));
+        assert_eq!(10, range(&img, 12..16));
+        assert!(range(&enhanced, 0..4) > 100);
+        assert!(range(&enhanced, 12..16) > 100);
+    }
+}
@@ -0,0 +1,97 @@
+// `Serialize` and `Deserialize` for `Image`, enabled by the `serde` feature
+
+use alloc::vec::Vec;
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use super::{Image, Pixel};
+
+// An image is serialized as its dimensions and its pixels in top-down row-major order. The
+// headers and the palette of a decoded file are not part of it.
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Image")]
+struct ImageRepr {
+    width: u32,
+    height: u32,
+    pixels: Vec<Pixel>,
+}
+
+impl Serialize for Image {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let pixels = self.coordinates().map(|(x, y)| self.get_pixel(x, y)).collect();
+        ImageRepr { width: self.width, height: self.height, pixels }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Image {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Image, D::Error> {
+        let repr = ImageRepr::deserialize(deserializer)?;
+        let size = (repr.width as usize).checked_mul(repr.height as usize);
+        if size != Some(repr.pixels.len()) {
+            return Err(de::Error::invalid_length(repr.pixels.len(), &"width * height pixels"));
+        }
+
+        let mut img = Image::new(repr.width, repr.height);
+        for ((x, y), &px) in img.coordinates().zip(repr.pixels.iter()) {
+            img.set_pixel(x, y, px);
+        }
+        Ok(img)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use serde_test::{assert_de_tokens_error, assert_tokens, Token};
+
+    use consts;
+    use {Image, Pixel};
+
+    fn pixel_tokens(px: Pixel) -> [Token; 8] {
+        [
+            Token::Struct { name: "Pixel", len: 3 },
+            Token::Str("r"),
+            Token::U8(px.r),
+            Token::Str("g"),
+            Token::U8(px.g),
+            Token::Str("b"),
+            Token::U8(px.b),
+            Token::StructEnd,
+        ]
+    }
+
+    fn image_tokens(width: u32, height: u32, pixels: &[Pixel]) -> Vec<Token> {
+        let mut tokens = alloc::vec![
+            Token::Struct { name: "Image", len: 3 },
+            Token::Str("width"),
+            Token::U32(width),
+            Token::Str("height"),
+            Token::U32(height),
+            Token::Str("pixels"),
+            Token::Seq { len: Some(pixels.len()) },
+        ];
+        for &px in pixels {
+            tokens.extend_from_slice(&pixel_tokens(px));
+        }
+        tokens.extend_from_slice(&[Token::SeqEnd, Token::StructEnd]);
+        tokens
+    }
+
+    #[test]
+    fn image_round_trip() {
+        let mut img = Image::new(2, 2);
+        img.set_pixel(1, 0, consts::RED);
+        img.set_pixel(0, 1, consts::BLUE);
+        let pixels = [consts::BLACK, consts::RED, consts::BLUE, consts::BLACK];
+        assert_tokens(&img, &image_tokens(2, 2, &pixels));
+    }
+
+    #[test]
+    fn reject_wrong_pixel_count() {
+        assert_de_tokens_error::<Image>(
+            &image_tokens(2, 2, &[consts::BLACK; 3]),
+            "invalid length 3, expected width * height pixels",
+        );
+    }
+}
@@ -0,0 +1,26 @@
+#![no_main]
+#![no_std]
+
+use risc0_zkvm_guest::{env, sha};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use bmp::{Image, Rect};
+use zkedit::ImageData;
+
+risc0_zkvm_guest::entry!(main);
+
+pub fn main() {
+    let file_bytes: Vec<u8> = env::read();
+    let (x, y, width, height): (u32, u32, u32, u32) = env::read();
+
+    let image: Image = bmp::from_bytes(&file_bytes).unwrap();
+    let cropped = image.crop(Rect::new(x, y, width, height)).unwrap();
+
+    // The journal is the crop rectangle, followed by the digests of the original and the
+    // cropped pixels
+    env::commit(&(x, y, width, height));
+    env::commit(&sha::digest(&ImageData::from_image(&image)));
+    env::commit(&sha::digest(&ImageData::from_image(&cropped)));
+}
@@ -13,25 +13,12 @@ risc0_zkvm_guest::entry!(main);
 
 pub fn main() {
     let file_bytes: Vec<u8> = env::read();
-    env::commit(&sha::digest(&file_bytes));
 
-    /*
     let image: Image = bmp::from_bytes(&file_bytes).unwrap();
-    let mut data: ImageData = ImageData {
-        width: image.get_width(),
-        height: image.get_height(),
-        pixels: Vec::new()
-    };
-
-    /*
-    for _ in 0..data.height {
-        let mut row: Vec<u32> = Vec::new();
-        for x in 0..data.width {
-            row.push(x);
-        }
-        data.pixels.push(row);
-    }
-    */
+    let data = ImageData::from_image(&image);
+    // The journal is the length of the file, the width, the height, and the digest of the pixels
+    env::commit(&(file_bytes.len() as u32));
+    env::commit(&image.get_width());
+    env::commit(&image.get_height());
     env::commit(&sha::digest(&data));
-    */
 }
@@ -0,0 +1,23 @@
+#![no_main]
+#![no_std]
+
+use risc0_zkvm_guest::env;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use bmp::{Image};
+use zkedit::ImagePhash;
+
+risc0_zkvm_guest::entry!(main);
+
+pub fn main() {
+    let file_bytes: Vec<u8> = env::read();
+
+    let image: Image = bmp::from_bytes(&file_bytes).unwrap();
+    env::commit(&ImagePhash {
+        width: image.get_width(),
+        height: image.get_height(),
+        phash: zkedit::phash(&image),
+    });
+}
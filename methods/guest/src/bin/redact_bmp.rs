@@ -0,0 +1,33 @@
+#![no_main]
+#![no_std]
+
+use risc0_zkvm_guest::{env, sha};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use bmp::{consts, Image};
+use zkedit::ImageData;
+
+risc0_zkvm_guest::entry!(main);
+
+pub fn main() {
+    let file_bytes: Vec<u8> = env::read();
+    let rects: Vec<(u32, u32, u32, u32)> = env::read();
+
+    let image: Image = bmp::from_bytes(&file_bytes).unwrap();
+    let mut redacted = image.clone();
+    for &(x, y, width, height) in rects.iter() {
+        // `fill_rect` would clip the rectangle, so a rectangle outside of the image fails the proof
+        image
+            .sub_image(x, y, width, height)
+            .expect("The redacted rectangles must lie within the image");
+        redacted.fill_rect(x, y, width, height, consts::BLACK);
+    }
+
+    // The journal is the list of rectangles, followed by the digests of the original and the
+    // redacted pixels
+    env::commit(&rects);
+    env::commit(&sha::digest(&ImageData::from_image(&image)));
+    env::commit(&sha::digest(&ImageData::from_image(&redacted)));
+}
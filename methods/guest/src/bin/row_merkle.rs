@@ -0,0 +1,25 @@
+#![no_main]
+#![no_std]
+
+use risc0_zkvm_guest::env;
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use bmp::Image;
+use zkedit::ImageData;
+
+risc0_zkvm_guest::entry!(main);
+
+pub fn main() {
+    let file_bytes: Vec<u8> = env::read();
+
+    let image: Image = bmp::from_bytes(&file_bytes).unwrap();
+    let data = ImageData::from_image(&image);
+
+    // The journal is the width, the height, and the root of the Merkle tree over the row
+    // digests, so a verifier can later check single rows against it
+    env::commit(&data.width);
+    env::commit(&data.height);
+    env::commit(&zkedit::row_merkle_root(&data));
+}
@@ -0,0 +1,25 @@
+#![no_main]
+#![no_std]
+
+use risc0_zkvm_guest::{env, sha};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+risc0_zkvm_guest::entry!(main);
+
+pub fn main() {
+    let file_bytes: Vec<u8> = env::read();
+
+    let mut pixels = Vec::new();
+    let (width, height) = bmp::decode_into(&file_bytes, &mut pixels).unwrap();
+    let [r, g, b] = zkedit::split_rgb(&pixels);
+
+    // The journal is the width, the height, and the digests of the red, green, and blue planes
+    // in the bottom-up row order of the file
+    env::commit(&width);
+    env::commit(&height);
+    env::commit(&sha::digest(&r));
+    env::commit(&sha::digest(&g));
+    env::commit(&sha::digest(&b));
+}
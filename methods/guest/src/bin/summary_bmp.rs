@@ -0,0 +1,21 @@
+#![no_main]
+#![no_std]
+
+use risc0_zkvm_guest::{env, sha};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use bmp::{Image};
+use zkedit::{ImageData, ImageSummary};
+
+risc0_zkvm_guest::entry!(main);
+
+pub fn main() {
+    let file_bytes: Vec<u8> = env::read();
+
+    let image: Image = bmp::from_bytes(&file_bytes).unwrap();
+    // The summary is followed by the digest of the pixels, which binds it to a specific image
+    env::commit(&ImageSummary::from_image(&image));
+    env::commit(&sha::digest(&ImageData::from_image(&image)));
+}
@@ -0,0 +1,30 @@
+#![no_main]
+#![no_std]
+
+use risc0_zkvm_guest::{env, sha};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use bmp::{Image};
+use zkedit::ImageData;
+
+risc0_zkvm_guest::entry!(main);
+
+pub fn main() {
+    let file_bytes: Vec<u8> = env::read();
+    let logo_bytes: Vec<u8> = env::read();
+    let (x, y): (u32, u32) = env::read();
+
+    let image: Image = bmp::from_bytes(&file_bytes).unwrap();
+    let logo: Image = bmp::from_bytes(&logo_bytes).unwrap();
+    let mut watermarked = image.clone();
+    watermarked.blit(&logo, x, y);
+
+    // The journal is the position of the logo, followed by the digests of the logo, the
+    // original and the watermarked pixels
+    env::commit(&(x, y));
+    env::commit(&sha::digest(&ImageData::from_image(&logo)));
+    env::commit(&sha::digest(&ImageData::from_image(&image)));
+    env::commit(&sha::digest(&ImageData::from_image(&watermarked)));
+}
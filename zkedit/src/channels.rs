@@ -0,0 +1,36 @@
+use alloc::vec::Vec;
+use bmp::Pixel;
+
+/// Splits a flat buffer of pixels, such as the one filled by `bmp::decode_into`, into its red,
+/// green, and blue planes. Each plane keeps the order of the buffer.
+///
+/// This works on the decoded buffer directly rather than on an `Image`, so no pixel is copied
+/// or indexed more than once, which keeps the cost low inside the guest.
+pub fn split_rgb(pixels: &[Pixel]) -> [Vec<u8>; 3] {
+    let mut planes = [
+        Vec::with_capacity(pixels.len()),
+        Vec::with_capacity(pixels.len()),
+        Vec::with_capacity(pixels.len()),
+    ];
+    for px in pixels {
+        planes[0].push(px.r);
+        planes[1].push(px.g);
+        planes[2].push(px.b);
+    }
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_rgb_keeps_the_buffer_order() {
+        let pixels = [Pixel::new(1, 2, 3), Pixel::new(4, 5, 6), Pixel::new(255, 0, 128)];
+        let [r, g, b] = split_rgb(&pixels);
+        assert_eq!(vec![1, 4, 255], r);
+        assert_eq!(vec![2, 5, 0], g);
+        assert_eq!(vec![3, 6, 128], b);
+        assert_eq!([Vec::<u8>::new(), Vec::new(), Vec::new()], split_rgb(&[]));
+    }
+}
@@ -1,13 +1,214 @@
-//#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_std)]
+//! Types and helpers shared by the zkedit guests and the host.
+//!
+//! All guests read the BMP file with `env::read::<Vec<u8>>()`, so the host has to send it
+//! serialized with the risc0 serde format: `prover.add_input(&to_vec(&file_bytes)?)`. Packing
+//! the bytes into words by hand does not produce the length prefix the guest expects. Any
+//! further input, such as the crop rectangle, is serialized the same way and read in order.
+//! `ImageProver` does both on the host, and `prove_redaction` wraps the redaction guest.
 
 extern crate alloc;
 
+use alloc::string::String;
 use alloc::vec::Vec;
+use bmp::{BmpError, BmpErrorKind, BmpResult, Image, Pixel};
 use serde::{Deserialize, Serialize};
 
+mod channels;
+mod merkle;
+mod phash;
+#[cfg(not(target_os = "zkvm"))]
+mod prover;
+mod region;
+pub use channels::split_rgb;
+pub use merkle::{
+    digest, merkle_root, pixel_merkle_proof, pixel_merkle_root, row_digests, row_merkle_root,
+    MerkleProof, Sibling,
+};
+pub use phash::{phash, phash_distance};
+pub use region::region_digest;
+#[cfg(not(target_os = "zkvm"))]
+pub use prover::{prove_redaction, ImageProver};
+
 #[derive(Clone, Debug, Eq, Deserialize, Serialize, PartialEq, Hash)]
 pub struct ImageData {
     pub width: u32,
     pub height: u32,
     pub pixels: Vec<Vec<u32>>,
-}
\ No newline at end of file
+}
+
+impl ImageData {
+    /// Converts `img` into rows of pixels from top to bottom, each pixel packed as `0x00RRGGBB`
+    /// by `u32::from(Pixel)`.
+    ///
+    /// This is the representation committed by the guests, so the host should use it as well
+    /// when recomputing a digest.
+    pub fn from_image(img: &Image) -> ImageData {
+        let pixels = (0..img.get_height())
+            .map(|y| {
+                (0..img.get_width())
+                    .map(|x| u32::from(img.get_pixel(x, y)))
+                    .collect()
+            })
+            .collect();
+        ImageData {
+            width: img.get_width(),
+            height: img.get_height(),
+            pixels,
+        }
+    }
+
+    /// Unpacks the rows of `0x00RRGGBB` pixels back into an `Image`, for example to save a
+    /// committed `ImageData` on the host.
+    ///
+    /// Returns an error if there aren't `height` rows of `width` pixels each.
+    pub fn to_image(&self) -> BmpResult<Image> {
+        let rows_match = self.pixels.len() == self.height as usize;
+        if !rows_match || self.pixels.iter().any(|row| row.len() != self.width as usize) {
+            return Err(BmpError {
+                kind: BmpErrorKind::DimensionMismatch,
+                details: String::from("The pixel rows don't match the width and height"),
+                offset: None,
+            });
+        }
+
+        let mut img = Image::new(self.width, self.height);
+        for (y, row) in self.pixels.iter().enumerate() {
+            for (x, &p) in row.iter().enumerate() {
+                img.set_pixel(x as u32, y as u32, Pixel::from(p));
+            }
+        }
+        Ok(img)
+    }
+}
+
+/// The sizes in bytes of an image at each step of the proving pipeline, from `report_sizes`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct SizeReport {
+    /// The size of the original BMP file.
+    pub original: usize,
+    /// The size of the decoded pixels of the edited image in memory.
+    pub decoded: usize,
+    /// The size of the edited image re-encoded as a 24-bit BMP file.
+    pub encoded: usize,
+    /// The size of the serialized `ImageData` of the edited image, which is what the guest
+    /// digests and what a journal containing it would hold.
+    pub committed: usize,
+}
+
+/// Reports how the size of `original`, edited into `edited`, changes between the file, the
+/// decoded image, the re-encoded file, and the data committed by the guest.
+pub fn report_sizes(original: &[u8], edited: &Image) -> SizeReport {
+    let (width, height) = (edited.get_width() as usize, edited.get_height() as usize);
+    SizeReport {
+        original: original.len(),
+        decoded: width * height * core::mem::size_of::<Pixel>(),
+        encoded: edited.to_bytes().len(),
+        // One word each for the width, the height, and the number of rows, and one word per
+        // pixel plus the length of every row
+        committed: 4 * (3 + height * (1 + width)),
+    }
+}
+
+/// The public output of the `phash_bmp` guest: the dimensions and the perceptual hash of the
+/// decoded image.
+#[derive(Clone, Copy, Debug, Eq, Deserialize, Serialize, PartialEq, Hash)]
+pub struct ImagePhash {
+    pub width: u32,
+    pub height: u32,
+    pub phash: u64,
+}
+
+/// Number of buckets of each channel histogram in an `ImageSummary`.
+pub const SUMMARY_BUCKETS: usize = 16;
+
+/// The public output of the `summary_bmp` guest: a few verifiable facts about the pixels of the
+/// decoded image, without the pixels themselves.
+#[derive(Clone, Copy, Debug, Eq, Deserialize, Serialize, PartialEq, Hash)]
+pub struct ImageSummary {
+    pub width: u32,
+    pub height: u32,
+    /// The mean of each channel, rounded to the nearest integer. Zero for an empty image.
+    pub mean_rgb: [u8; 3],
+    /// The number of pixels in each range of 16 values, indexed as `[r, g, b][value / 16]`.
+    pub histogram_buckets: [[u32; SUMMARY_BUCKETS]; 3],
+}
+
+impl ImageSummary {
+    /// Summarizes `img`, the same way the guest does.
+    pub fn from_image(img: &Image) -> ImageSummary {
+        let mut histogram_buckets = [[0; SUMMARY_BUCKETS]; 3];
+        for (buckets, histogram) in histogram_buckets.iter_mut().zip(img.histogram().iter()) {
+            for (v, &n) in histogram.iter().enumerate() {
+                buckets[v * SUMMARY_BUCKETS / 256] += n;
+            }
+        }
+        let mean = img.mean_color();
+        ImageSummary {
+            width: img.get_width(),
+            height: img.get_height(),
+            mean_rgb: [mean.r, mean.g, mean.b],
+            histogram_buckets,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bmp::consts;
+
+    #[test]
+    fn from_image_packs_rows_top_down() {
+        let mut img = Image::new(3, 2);
+        img.set_pixel(0, 0, consts::RED);
+        img.set_pixel(2, 0, Pixel::new(0x12, 0x34, 0x56));
+        img.set_pixel(1, 1, consts::WHITE);
+
+        let data = ImageData::from_image(&img);
+        assert_eq!((3, 2), (data.width, data.height));
+        assert_eq!(vec![vec![0xFF0000, 0, 0x123456], vec![0, 0xFFFFFF, 0]], data.pixels);
+        assert_eq!(img, data.to_image().unwrap());
+    }
+
+    #[test]
+    fn report_sizes_of_a_small_edit() {
+        let original = Image::new(4, 4).to_bytes();
+        let edited = Image::new(3, 2);
+        let report = report_sizes(&original, &edited);
+        // 54 bytes of headers and four rows of 12 bytes
+        assert_eq!(54 + 48, report.original);
+        assert_eq!(18, report.decoded);
+        // Rows of 9 bytes are padded to 12
+        assert_eq!(54 + 24, report.encoded);
+        assert_eq!(4 * (3 + 2 * 4), report.committed);
+    }
+
+    #[test]
+    fn summary_of_a_two_color_image() {
+        let mut img = Image::new(4, 2);
+        img.fill_rect(0, 0, 3, 2, Pixel::new(255, 16, 100));
+
+        let summary = ImageSummary::from_image(&img);
+        assert_eq!((4, 2), (summary.width, summary.height));
+        // Six of eight pixels are colored, e.g. red is 6 * 255 / 8 = 191.25
+        assert_eq!([191, 12, 75], summary.mean_rgb);
+        assert_eq!((2, 6), (summary.histogram_buckets[0][0], summary.histogram_buckets[0][15]));
+        assert_eq!((2, 6), (summary.histogram_buckets[1][0], summary.histogram_buckets[1][1]));
+        assert_eq!((2, 6), (summary.histogram_buckets[2][0], summary.histogram_buckets[2][6]));
+        for channel in summary.histogram_buckets.iter() {
+            assert_eq!(8, channel.iter().sum::<u32>());
+        }
+
+        assert_eq!([0; 3], ImageSummary::from_image(&Image::new(0, 0)).mean_rgb);
+    }
+
+    #[test]
+    fn to_image_rejects_mismatched_rows() {
+        let mut data = ImageData::from_image(&Image::new(2, 2));
+        data.pixels[1].pop();
+        assert!(data.to_image().is_err());
+        data.pixels.pop();
+        assert!(data.to_image().is_err());
+    }
+}
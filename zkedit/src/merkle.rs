@@ -0,0 +1,200 @@
+//! Merkle trees over the rows or the pixels of an image, so a verifier can check that individual
+//! rows or pixels were part of a proven image without the guest committing all of them.
+//!
+//! Hashing goes through the software SHA-256 of `risc0-zkvm-core`, so the guest and the host
+//! compute the same digests with the same code.
+
+use alloc::vec::Vec;
+use risc0_zkvm_core::sha::{default_implementation, Digest, Sha};
+use risc0_zkvm_serde::to_vec;
+use serde::{Deserialize, Serialize};
+
+use crate::ImageData;
+
+/// Returns the SHA-256 of the risc0 serialization of `value`, the same digest as the guest's
+/// `sha::digest(value)`, so the host can recompute what a guest committed.
+pub fn digest<T: Serialize + ?Sized>(value: &T) -> Digest {
+    *default_implementation().hash_words(&to_vec(value).unwrap())
+}
+
+/// Returns the digest of every row of `data`, from top to bottom.
+pub fn row_digests(data: &ImageData) -> Vec<Digest> {
+    data.pixels.iter().map(|row| digest(row)).collect()
+}
+
+/// Returns the root of the Merkle tree whose leaves are `leaves`.
+///
+/// Every parent is the digest of the serialized pair of its children, and a child without a
+/// sibling moves up a level unchanged. The root of a single leaf is the leaf itself, and the
+/// root of no leaves is the digest of an empty input.
+pub fn merkle_root(leaves: &[Digest]) -> Digest {
+    if leaves.is_empty() {
+        return digest(&());
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+// Returns the parents of the nodes of a level of a Merkle tree
+fn next_level(level: &[Digest]) -> Vec<Digest> {
+    level
+        .chunks(2)
+        .map(|pair| match *pair {
+            [left, right] => digest(&(left, right)),
+            [single] => single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+// Returns the siblings needed to recompute the root from the leaf at `index`, from the bottom of
+// the tree to the top
+fn merkle_path(leaves: &[Digest], mut index: usize) -> Vec<Sibling> {
+    let mut level = leaves.to_vec();
+    let mut path = Vec::new();
+    while level.len() > 1 {
+        if index % 2 == 1 {
+            path.push(Sibling::Left(level[index - 1]));
+        } else if let Some(&right) = level.get(index + 1) {
+            path.push(Sibling::Right(right));
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    path
+}
+
+/// Returns the root of the Merkle tree over the row digests of `data`.
+pub fn row_merkle_root(data: &ImageData) -> Digest {
+    merkle_root(&row_digests(data))
+}
+
+/// A sibling on the path from a leaf to the root of a Merkle tree.
+#[derive(Clone, Copy, Debug, Eq, Deserialize, Serialize, PartialEq)]
+pub enum Sibling {
+    /// The sibling is the left child, so the running digest is the right one.
+    Left(Digest),
+    /// The sibling is the right child, so the running digest is the left one.
+    Right(Digest),
+}
+
+/// A proof that the pixel at `(x, y)` had the color `pixel` in an image with a given
+/// `pixel_merkle_root`.
+#[derive(Clone, Debug, Eq, Deserialize, Serialize, PartialEq)]
+pub struct MerkleProof {
+    pub x: u32,
+    pub y: u32,
+    /// The color of the pixel, packed as `0x00RRGGBB`.
+    pub pixel: u32,
+    pub siblings: Vec<Sibling>,
+}
+
+impl MerkleProof {
+    /// Returns `true` if the proof leads from its pixel to `root`.
+    pub fn verify(&self, root: &[u8; 32]) -> bool {
+        let leaf = pixel_leaf(self.x, self.y, self.pixel);
+        let computed = self
+            .siblings
+            .iter()
+            .fold(leaf, |acc, sibling| match *sibling {
+                Sibling::Left(left) => digest(&(left, acc)),
+                Sibling::Right(right) => digest(&(acc, right)),
+            });
+        digest_bytes(&computed) == *root
+    }
+}
+
+// A leaf of a pixel Merkle tree is the digest of the coordinates of the pixel followed by its
+// packed color, so a proof can't be moved to another pixel
+fn pixel_leaf(x: u32, y: u32, pixel: u32) -> Digest {
+    digest(&(x, y, pixel))
+}
+
+fn pixel_leaves(data: &ImageData) -> Vec<Digest> {
+    let mut leaves = Vec::with_capacity((data.width * data.height) as usize);
+    for (y, row) in data.pixels.iter().enumerate() {
+        for (x, &pixel) in row.iter().enumerate() {
+            leaves.push(pixel_leaf(x as u32, y as u32, pixel));
+        }
+    }
+    leaves
+}
+
+// Returns the digest as bytes, each word being big-endian as in the usual SHA-256 output
+pub(crate) fn digest_bytes(digest: &Digest) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    for (chunk, word) in bytes.chunks_mut(4).zip(to_vec(digest).unwrap()) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    bytes
+}
+
+/// Returns the root of the Merkle tree over the pixels of `data`, from left to right and from top
+/// to bottom.
+pub fn pixel_merkle_root(data: &ImageData) -> [u8; 32] {
+    digest_bytes(&merkle_root(&pixel_leaves(data)))
+}
+
+/// Returns the proof that the pixel at `(x, y)` of `data` is part of its `pixel_merkle_root`.
+///
+/// # Panics
+///
+/// Panics if `(x, y)` is outside of the image.
+pub fn pixel_merkle_proof(data: &ImageData, x: u32, y: u32) -> MerkleProof {
+    assert!(
+        x < data.width && y < data.height,
+        "The pixel must lie within the image"
+    );
+    let index = (y * data.width + x) as usize;
+    MerkleProof {
+        x,
+        y,
+        pixel: data.pixels[y as usize][x as usize],
+        siblings: merkle_path(&pixel_leaves(data), index),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bmp::Image;
+
+    #[test]
+    fn merkle_root_pairs_leaves() {
+        let (a, b, c) = (digest(&1u32), digest(&2u32), digest(&3u32));
+        assert_eq!(a, merkle_root(&[a]));
+        assert_eq!(digest(&(a, b)), merkle_root(&[a, b]));
+        // The odd leaf moves up unchanged
+        assert_eq!(digest(&(digest(&(a, b)), c)), merkle_root(&[a, b, c]));
+    }
+
+    #[test]
+    fn pixel_merkle_proofs_verify() {
+        let mut img = Image::new(3, 3);
+        img.fill_rect(1, 0, 2, 2, bmp::consts::RED);
+        let data = ImageData::from_image(&img);
+        let root = pixel_merkle_root(&data);
+        for (x, y) in img.coordinates() {
+            assert!(pixel_merkle_proof(&data, x, y).verify(&root));
+        }
+
+        let mut tampered = pixel_merkle_proof(&data, 2, 1);
+        tampered.pixel = u32::from(bmp::consts::BLUE);
+        assert!(!tampered.verify(&root));
+        let mut moved = pixel_merkle_proof(&data, 2, 1);
+        moved.x = 1;
+        assert!(!moved.verify(&root));
+    }
+
+    #[test]
+    fn row_merkle_root_changes_with_any_row() {
+        let mut img = Image::new(4, 5);
+        let root = row_merkle_root(&ImageData::from_image(&img));
+        assert_eq!(5, row_digests(&ImageData::from_image(&img)).len());
+        img.set_pixel(3, 4, bmp::consts::RED);
+        assert_ne!(root, row_merkle_root(&ImageData::from_image(&img)));
+    }
+}
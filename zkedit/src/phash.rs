@@ -0,0 +1,120 @@
+//! A perceptual hash of images, so a verifier can check that a candidate image looks like a
+//! proven one without having access to the original. Only integer arithmetic is used, so the
+//! hash is cheap to compute inside the guest and identical on the host.
+
+use bmp::Image;
+
+// Side of the grayscale thumbnail the DCT is computed on
+const SIZE: usize = 32;
+// Number of low frequencies kept in each direction
+const FREQS: usize = 8;
+
+// COSINES[u][x] = cos((2x + 1) * u * pi / 64), scaled by 4096
+const COSINES: [[i64; SIZE]; FREQS] = [
+    [
+        4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096,
+        4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096,
+        4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096, 4096,
+    ],
+    [
+        4091, 4052, 3973, 3857, 3703, 3513, 3290, 3035, 2751, 2440, 2106,
+        1751, 1380, 995, 601, 201, -201, -601, -995, -1380, -1751, -2106,
+        -2440, -2751, -3035, -3290, -3513, -3703, -3857, -3973, -4052, -4091,
+    ],
+    [
+        4076, 3920, 3612, 3166, 2598, 1931, 1189, 401, -401, -1189, -1931,
+        -2598, -3166, -3612, -3920, -4076, -4076, -3920, -3612, -3166, -2598, -1931,
+        -1189, -401, 401, 1189, 1931, 2598, 3166, 3612, 3920, 4076,
+    ],
+    [
+        4052, 3703, 3035, 2106, 995, -201, -1380, -2440, -3290, -3857, -4091,
+        -3973, -3513, -2751, -1751, -601, 601, 1751, 2751, 3513, 3973, 4091,
+        3857, 3290, 2440, 1380, 201, -995, -2106, -3035, -3703, -4052,
+    ],
+    [
+        4017, 3406, 2276, 799, -799, -2276, -3406, -4017, -4017, -3406, -2276,
+        -799, 799, 2276, 3406, 4017, 4017, 3406, 2276, 799, -799, -2276,
+        -3406, -4017, -4017, -3406, -2276, -799, 799, 2276, 3406, 4017,
+    ],
+    [
+        3973, 3035, 1380, -601, -2440, -3703, -4091, -3513, -2106, -201, 1751,
+        3290, 4052, 3857, 2751, 995, -995, -2751, -3857, -4052, -3290, -1751,
+        201, 2106, 3513, 4091, 3703, 2440, 601, -1380, -3035, -3973,
+    ],
+    [
+        3920, 2598, 401, -1931, -3612, -4076, -3166, -1189, 1189, 3166, 4076,
+        3612, 1931, -401, -2598, -3920, -3920, -2598, -401, 1931, 3612, 4076,
+        3166, 1189, -1189, -3166, -4076, -3612, -1931, 401, 2598, 3920,
+    ],
+    [
+        3857, 2106, -601, -3035, -4091, -3290, -995, 1751, 3703, 3973, 2440,
+        -201, -2751, -4052, -3513, -1380, 1380, 3513, 4052, 2751, 201, -2440,
+        -3973, -3703, -1751, 995, 3290, 4091, 3035, 601, -2106, -3857,
+    ],
+];
+
+/// Returns the 64-bit perceptual hash of `image`.
+///
+/// The image is reduced to a 32x32 grayscale thumbnail by averaging the luma of each block of
+/// pixels, and the lowest 8x8 frequencies of its (unnormalized) DCT-II are kept. Each bit of the
+/// hash, in row-major order of the frequencies, is set if that coefficient is above the median of
+/// the 63 coefficients other than the DC term.
+///
+/// Similar images have hashes with a small `phash_distance`. An image with no pixels hashes to 0.
+pub fn phash(image: &Image) -> u64 {
+    let (width, height) = (image.get_width() as u64, image.get_height() as u64);
+    if width == 0 || height == 0 {
+        return 0;
+    }
+
+    // Every cell of the thumbnail covers at least one pixel
+    let span = |cell: usize, len: u64| {
+        let start = cell as u64 * len / SIZE as u64;
+        let end = (cell as u64 + 1) * len / SIZE as u64;
+        (start as u32, end.max(start + 1) as u32)
+    };
+    let mut gray = [[0i64; SIZE]; SIZE];
+    for (cy, row) in gray.iter_mut().enumerate() {
+        let (y0, y1) = span(cy, height);
+        for (cx, cell) in row.iter_mut().enumerate() {
+            let (x0, x1) = span(cx, width);
+            let mut sum = 0;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    sum += image.get_pixel(x, y).luminance() as i64;
+                }
+            }
+            *cell = sum / ((x1 - x0) as i64 * (y1 - y0) as i64);
+        }
+    }
+
+    // Separable DCT, first along the columns, then along the rows
+    let mut columns = [[0i64; SIZE]; FREQS];
+    for (v, row) in columns.iter_mut().enumerate() {
+        for (x, value) in row.iter_mut().enumerate() {
+            *value = (0..SIZE).map(|y| COSINES[v][y] * gray[y][x]).sum();
+        }
+    }
+    let mut coefficients = [0i64; FREQS * FREQS];
+    for v in 0..FREQS {
+        for u in 0..FREQS {
+            coefficients[v * FREQS + u] = (0..SIZE).map(|x| COSINES[u][x] * columns[v][x]).sum();
+        }
+    }
+
+    let mut ac = [0i64; FREQS * FREQS - 1];
+    ac.copy_from_slice(&coefficients[1..]);
+    ac.sort_unstable();
+    let median = ac[ac.len() / 2];
+
+    coefficients
+        .iter()
+        .enumerate()
+        .filter(|&(_, &c)| c > median)
+        .fold(0, |hash, (i, _)| hash | 1 << (63 - i))
+}
+
+/// Returns the number of bits that differ between two perceptual hashes.
+pub fn phash_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
@@ -0,0 +1,91 @@
+//! Host-side helper to prove a guest method over a BMP file.
+
+extern crate std;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use bmp::{Image, Rect};
+use methods::{REDACT_BMP_ID, REDACT_BMP_PATH};
+use risc0_zkvm_host::{Prover, Receipt};
+use risc0_zkvm_serde::to_vec;
+use serde::Serialize;
+
+/// Runs a guest method on serialized inputs and returns its verified receipt, replacing the
+/// `Prover::new`, `add_input`, `run` and `verify` steps repeated for every guest.
+///
+/// Inputs are serialized with the risc0 serde format and sent in the order they are added,
+/// matching the `env::read` calls of the guest.
+///
+/// # Example
+///
+/// ```ignore
+/// let receipt = ImageProver::new(EXTRACT_BMP_PATH, EXTRACT_BMP_ID)
+///     .with_image_bytes(&file_bytes)
+///     .prove();
+/// ```
+pub struct ImageProver<'a> {
+    method_path: &'a str,
+    method_id: &'a [u8],
+    inputs: Vec<Vec<u32>>,
+}
+
+impl<'a> ImageProver<'a> {
+    /// Creates a prover for the guest ELF at `method_path`, with the ID generated for it by
+    /// `risc0_build::embed_methods`.
+    pub fn new(method_path: &'a str, method_id: &'a [u8]) -> ImageProver<'a> {
+        ImageProver {
+            method_path,
+            method_id,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Sends the bytes of a BMP file, read by the guest with `env::read::<Vec<u8>>()`.
+    pub fn with_image_bytes(self, bytes: &[u8]) -> ImageProver<'a> {
+        self.with_input(bytes)
+    }
+
+    /// Sends any further input, such as the crop rectangle.
+    pub fn with_input<T: Serialize + ?Sized>(mut self, value: &T) -> ImageProver<'a> {
+        self.inputs.push(to_vec(value).unwrap());
+        self
+    }
+
+    /// Runs the guest and verifies the receipt against the method ID before returning it.
+    ///
+    /// # Panics
+    ///
+    /// If the guest ELF can't be read, or the method fails to run or to verify.
+    pub fn prove(&self) -> Receipt {
+        let method_code = std::fs::read(self.method_path).expect("The guest ELF should exist");
+        let mut prover = Prover::new(&method_code, self.method_id).unwrap();
+        for input in self.inputs.iter() {
+            prover.add_input(input).unwrap();
+        }
+        let receipt = prover.run().expect(
+            "Valid code should be provable if it doesn't overflow the cycle limit. See \
+             `embed_methods_with_options` for information on adjusting maximum cycle count.",
+        );
+        receipt.verify(self.method_id).expect(
+            "Code you have proven should successfully verify; did you specify the correct \
+             method ID?",
+        );
+        receipt
+    }
+}
+
+/// Proves that `rect` of `original` was blacked out, with the `redact_bmp` guest, and returns
+/// the verified receipt.
+///
+/// The journal holds the redacted rectangles, followed by the digests of the original and the
+/// redacted `ImageData`.
+///
+/// # Panics
+///
+/// If `rect` doesn't lie within the image, or the proof fails like in `ImageProver::prove`.
+pub fn prove_redaction(original: &Image, rect: Rect) -> Receipt {
+    ImageProver::new(REDACT_BMP_PATH, REDACT_BMP_ID)
+        .with_image_bytes(&original.to_bytes())
+        .with_input(&vec![(rect.x, rect.y, rect.width, rect.height)])
+        .prove()
+}
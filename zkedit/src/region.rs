@@ -0,0 +1,65 @@
+//! Digests of a rectangular region of an image, so a guest can attest to one area without
+//! committing or revealing the rest of the pixels.
+
+use alloc::vec::Vec;
+
+use crate::merkle::{digest, digest_bytes};
+use crate::ImageData;
+
+/// Returns the SHA-256 of the `w` x `h` region of `img` whose upper left corner is at `(x, y)`.
+///
+/// The digest is over the rows of the region from top to bottom, packed and serialized like the
+/// `pixels` of an `ImageData`, so it equals the guest's `sha::digest` of the cropped rows and is
+/// the same on the host. The coordinates aren't part of the digest, commit them alongside it.
+///
+/// # Panics
+///
+/// Panics if the region doesn't lie within the image.
+pub fn region_digest(img: &ImageData, x: u32, y: u32, w: u32, h: u32) -> [u8; 32] {
+    let fits = |start: u32, len: u32, max: u32| {
+        start.checked_add(len).map_or(false, |end| end <= max)
+    };
+    assert!(
+        fits(x, w, img.width) && fits(y, h, img.height),
+        "The region must lie within the image"
+    );
+    let (x, w) = (x as usize, w as usize);
+    let rows: Vec<&[u32]> = img.pixels[y as usize..(y + h) as usize]
+        .iter()
+        .map(|row| &row[x..x + w])
+        .collect();
+    digest_bytes(&digest(&rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bmp::{consts, Image};
+    use risc0_zkvm_core::sha::{default_implementation, Sha};
+    use risc0_zkvm_serde::to_vec;
+
+    #[test]
+    fn region_digest_hashes_the_region_rows() {
+        let mut img = Image::new(4, 3);
+        img.set_pixel(1, 1, consts::RED);
+        img.set_pixel(3, 0, consts::WHITE);
+        let data = ImageData::from_image(&img);
+
+        let region: Vec<Vec<u32>> = vec![vec![0, 0], vec![0xFF0000, 0]];
+        let expected = default_implementation().hash_words(&to_vec(&region).unwrap());
+        assert_eq!(digest_bytes(&expected), region_digest(&data, 1, 0, 2, 2));
+
+        // Pixels outside of the region don't change its digest
+        let mut changed = img.clone();
+        changed.set_pixel(0, 2, consts::BLUE);
+        let changed = ImageData::from_image(&changed);
+        assert_eq!(region_digest(&data, 1, 0, 2, 2), region_digest(&changed, 1, 0, 2, 2));
+        assert_ne!(region_digest(&data, 1, 0, 2, 2), region_digest(&data, 2, 0, 2, 2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn region_digest_rejects_regions_outside_of_the_image() {
+        region_digest(&ImageData::from_image(&Image::new(4, 3)), 3, 0, 2, 1);
+    }
+}
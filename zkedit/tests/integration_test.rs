@@ -1,40 +1,201 @@
 use std::path::PathBuf;
 
-use zkedit::{ImageData};
+use zkedit::{digest, ImageData, ImagePhash, ImageProver, ImageSummary};
 
-use methods::{EXTRACT_BMP_ID, EXTRACT_BMP_PATH};
-use risc0_zkvm_host::Prover;
-// use risc0_zkvm::serde::{from_slice, to_vec};
+use methods::{
+    CROP_BMP_ID, CROP_BMP_PATH, EXTRACT_BMP_ID, EXTRACT_BMP_PATH, PHASH_BMP_ID, PHASH_BMP_PATH,
+    REDACT_BMP_ID, REDACT_BMP_PATH, ROW_MERKLE_ID, ROW_MERKLE_PATH, SPLIT_RGB_ID, SPLIT_RGB_PATH,
+    SUMMARY_BMP_ID, SUMMARY_BMP_PATH, WATERMARK_BMP_ID, WATERMARK_BMP_PATH,
+};
+use risc0_zkvm_core::sha::Digest;
+use risc0_zkvm_serde::from_slice;
 
-extern crate byteorder;
-
-use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
-
-#[test]
-fn run() {
-    // Make the prover.
-    let method_code = std::fs::read(EXTRACT_BMP_PATH).unwrap();
-    let mut prover = Prover::new(&method_code, EXTRACT_BMP_ID).unwrap();
+fn orig_bmp() -> Vec<u8> {
     let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     d.push("tests");
     d.push("img_orig.bmp");
-    
-    let file_bytes = std::fs::read(d).unwrap();
-    let mut u32_to_send: Vec<u32> = vec![];
-
-    for chunk in file_bytes.as_slice().chunks(4) {
-        let mut vec = [0u8; 4];
-        vec[..chunk.len()].copy_from_slice(chunk);
-        u32_to_send.push(vec.as_slice().read_u32::<BigEndian>().unwrap());
+    std::fs::read(d).unwrap()
+}
+
+#[test]
+fn image_prover_extract() {
+    let file_bytes = orig_bmp();
+    let receipt = ImageProver::new(EXTRACT_BMP_PATH, EXTRACT_BMP_ID)
+        .with_image_bytes(&file_bytes)
+        .prove();
+
+    // Decode the same file on the host and check the guest committed the digest of its pixels
+    let image = bmp::from_bytes(&file_bytes).unwrap();
+    let (len, width, height, committed): (u32, u32, u32, Digest) =
+        from_slice(&receipt.get_journal_vec().unwrap()).unwrap();
+    // The guest received the file unchanged
+    assert_eq!(file_bytes.len() as u32, len);
+    assert_eq!((700, 700), (width, height));
+    assert_eq!((image.get_width(), image.get_height()), (width, height));
+    assert_eq!(digest(&ImageData::from_image(&image)), committed);
+}
+
+#[test]
+fn phash() {
+    let file_bytes = orig_bmp();
+    let receipt = ImageProver::new(PHASH_BMP_PATH, PHASH_BMP_ID)
+        .with_image_bytes(&file_bytes)
+        .prove();
+
+    let committed: ImagePhash = from_slice(&receipt.get_journal_vec().unwrap()).unwrap();
+    let image = bmp::from_bytes(&file_bytes).unwrap();
+    assert_eq!((700, 700), (committed.width, committed.height));
+    assert_eq!(zkedit::phash(&image), committed.phash);
+
+    // A slightly edited copy is still perceptually the same image
+    let mut edited = image.clone();
+    edited.fill_rect(0, 0, 20, 20, bmp::consts::WHITE);
+    assert!(zkedit::phash_distance(committed.phash, zkedit::phash(&edited)) <= 8);
+}
+
+#[test]
+fn crop() {
+    let file_bytes = orig_bmp();
+    let rect = (100u32, 150u32, 64u32, 48u32);
+    let receipt = ImageProver::new(CROP_BMP_PATH, CROP_BMP_ID)
+        .with_image_bytes(&file_bytes)
+        .with_input(&rect)
+        .prove();
+
+    let image = bmp::from_bytes(&file_bytes).unwrap();
+    let cropped = image.crop(bmp::Rect::new(rect.0, rect.1, rect.2, rect.3)).unwrap();
+
+    let (committed_rect, original, thumbnail): ((u32, u32, u32, u32), Digest, Digest) =
+        from_slice(&receipt.get_journal_vec().unwrap()).unwrap();
+    assert_eq!(rect, committed_rect);
+    assert_eq!(digest(&ImageData::from_image(&image)), original);
+    assert_eq!(digest(&ImageData::from_image(&cropped)), thumbnail);
+}
+
+#[test]
+fn split_rgb() {
+    let file_bytes = orig_bmp();
+    let receipt = ImageProver::new(SPLIT_RGB_PATH, SPLIT_RGB_ID)
+        .with_image_bytes(&file_bytes)
+        .prove();
+
+    let mut pixels = Vec::new();
+    let dimensions = bmp::decode_into(&file_bytes, &mut pixels).unwrap();
+    let planes = zkedit::split_rgb(&pixels);
+
+    let (width, height, r, g, b): (u32, u32, Digest, Digest, Digest) =
+        from_slice(&receipt.get_journal_vec().unwrap()).unwrap();
+    assert_eq!(dimensions, (width, height));
+    assert_eq!([digest(&planes[0]), digest(&planes[1]), digest(&planes[2])], [r, g, b]);
+}
+
+#[test]
+fn row_merkle() {
+    let file_bytes = orig_bmp();
+    let receipt = ImageProver::new(ROW_MERKLE_PATH, ROW_MERKLE_ID)
+        .with_image_bytes(&file_bytes)
+        .prove();
+
+    let expected = ImageData::from_image(&bmp::from_bytes(&file_bytes).unwrap());
+
+    let (width, height, root): (u32, u32, Digest) =
+        from_slice(&receipt.get_journal_vec().unwrap()).unwrap();
+    assert_eq!((expected.width, expected.height), (width, height));
+    assert_eq!(zkedit::row_merkle_root(&expected), root);
+}
+
+#[test]
+fn summary() {
+    let file_bytes = orig_bmp();
+    let receipt = ImageProver::new(SUMMARY_BMP_PATH, SUMMARY_BMP_ID)
+        .with_image_bytes(&file_bytes)
+        .prove();
+
+    let image = bmp::from_bytes(&file_bytes).unwrap();
+    let data = ImageData::from_image(&image);
+
+    let (summary, committed_digest): (ImageSummary, Digest) =
+        from_slice(&receipt.get_journal_vec().unwrap()).unwrap();
+    assert_eq!(ImageSummary::from_image(&image), summary);
+    assert_eq!((700, 700), (summary.width, summary.height));
+    assert_eq!(700 * 700, summary.histogram_buckets[0].iter().sum::<u32>());
+    assert_eq!(digest(&data), committed_digest);
+}
+
+#[test]
+fn watermark() {
+    let file_bytes = orig_bmp();
+    let logo = bmp::Image::checkerboard(16, 8, bmp::consts::WHITE, bmp::consts::RED, 4);
+    let position = (600u32, 680u32);
+    let receipt = ImageProver::new(WATERMARK_BMP_PATH, WATERMARK_BMP_ID)
+        .with_image_bytes(&file_bytes)
+        .with_image_bytes(&logo.to_bytes())
+        .with_input(&position)
+        .prove();
+
+    let image = bmp::from_bytes(&file_bytes).unwrap();
+    let mut watermarked = image.clone();
+    watermarked.blit(&logo, position.0, position.1);
+    let image_digest = |img: &bmp::Image| digest(&ImageData::from_image(img));
+
+    let (committed_position, logo_digest, original, result): ((u32, u32), Digest, Digest, Digest) =
+        from_slice(&receipt.get_journal_vec().unwrap()).unwrap();
+    assert_eq!(position, committed_position);
+    assert_eq!(image_digest(&logo), logo_digest);
+    assert_eq!(image_digest(&image), original);
+    assert_eq!(image_digest(&watermarked), result);
+    assert_ne!(original, result);
+}
+
+#[test]
+fn redact() {
+    let file_bytes = orig_bmp();
+    let rects = vec![(10u32, 20u32, 100u32, 30u32), (0, 690, 700, 10)];
+    let receipt = ImageProver::new(REDACT_BMP_PATH, REDACT_BMP_ID)
+        .with_image_bytes(&file_bytes)
+        .with_input(&rects)
+        .prove();
+
+    let image = bmp::from_bytes(&file_bytes).unwrap();
+    let mut redacted = image.clone();
+    for &(x, y, width, height) in rects.iter() {
+        redacted.fill_rect(x, y, width, height, bmp::consts::BLACK);
     }
+    let image_digest = |img: &bmp::Image| digest(&ImageData::from_image(img));
 
-    prover.add_input(&u32_to_send.as_slice()).unwrap();
+    let (committed_rects, original, result): (Vec<(u32, u32, u32, u32)>, Digest, Digest) =
+        from_slice(&receipt.get_journal_vec().unwrap()).unwrap();
+    assert_eq!(rects, committed_rects);
+    assert_eq!(image_digest(&image), original);
+    assert_eq!(image_digest(&redacted), result);
+}
+
+#[test]
+#[should_panic]
+fn redact_rejects_rectangles_outside_of_the_image() {
+    ImageProver::new(REDACT_BMP_PATH, REDACT_BMP_ID)
+        .with_image_bytes(&orig_bmp())
+        .with_input(&vec![(650u32, 0u32, 100u32, 10u32)])
+        .prove();
+}
+
+#[test]
+fn prove_redaction() {
+    let original = bmp::from_bytes(&orig_bmp()).unwrap();
+    let rect = bmp::Rect::new(200, 300, 50, 40);
+    let receipt = zkedit::prove_redaction(&original, rect);
 
-    // Run prover & generate receipt
-    let receipt = prover.run()
-        .expect("Valid code should be provable if it doesn't overflow the cycle limit. See `embed_methods_with_options` for information on adjusting maximum cycle count.");
+    let (committed_rects, original_digest, redacted_digest): (
+        Vec<(u32, u32, u32, u32)>,
+        Digest,
+        Digest,
+    ) = from_slice(&receipt.get_journal_vec().unwrap()).unwrap();
+    assert_eq!(vec![(rect.x, rect.y, rect.width, rect.height)], committed_rects);
+    assert_eq!(digest(&ImageData::from_image(&original)), original_digest);
 
-    // Optional: Verify receipt to confirm that recipients will also be able to verify your receipt
-    receipt.verify(EXTRACT_BMP_ID)
-        .expect("Code you have proven should successfully verify; did you specify the correct method ID?");
-}
\ No newline at end of file
+    // The committed digest is the one of the original with only `rect` blacked out, so the
+    // proven image is black inside `rect` and matches `original` everywhere else
+    let mut redacted = original.clone();
+    redacted.fill_rect(rect.x, rect.y, rect.width, rect.height, bmp::consts::BLACK);
+    assert_eq!(digest(&ImageData::from_image(&redacted)), redacted_digest);
+}